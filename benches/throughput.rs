@@ -0,0 +1,202 @@
+// Measures how many packets per second a cacher serves from its cache.
+//
+//     cargo bench --bench throughput -- --clients 64 --seconds 10 --query player
+//
+// A fake upstream and the cacher run in this process on loopback, every client
+// repeatedly sends a query and waits for the complete response before sending
+// the next one.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time,
+};
+
+use clap::{Parser, ValueEnum};
+use steam_query_cacher::{config::ServerConfig, SteamQueryCacheServer};
+use tokio::{net::UdpSocket, task::JoinSet};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Query {
+    Info,
+    Player,
+}
+
+#[derive(Debug, Parser)]
+struct Args {
+    #[arg(long, default_value_t = 64)]
+    clients: usize,
+    #[arg(long, default_value_t = 10)]
+    seconds: u64,
+    #[arg(long, value_enum, default_value_t = Query::Info)]
+    query: Query,
+    // extra server settings merged into the config, e.g. '{"sockets": 4}'
+    #[arg(long, default_value = "{}")]
+    server: String,
+    // passed by cargo bench
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+fn cstr(value: &str) -> Vec<u8> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0x00);
+    bytes
+}
+
+fn info_reply() -> Vec<u8> {
+    let mut data = vec![0xFF, 0xFF, 0xFF, 0xFF, b'I', 17];
+    data.extend(cstr("Benchmark Server"));
+    data.extend(cstr("de_dust2"));
+    data.extend(cstr("cstrike"));
+    data.extend(cstr("Counter-Strike"));
+    data.extend(240i16.to_le_bytes());
+    data.extend([12, 64, 0, b'd', b'l', 0, 1]);
+    data.extend(cstr("1.0.0.0"));
+    data.push(0x00);
+    data
+}
+
+fn player_reply() -> Vec<u8> {
+    let mut data = vec![0xFF, 0xFF, 0xFF, 0xFF, b'D', 100];
+    for i in 0..100u8 {
+        data.push(i);
+        data.extend(cstr(&format!("player_{:03}_with_a_long_name", i)));
+        data.extend((i as i32).to_le_bytes());
+        data.extend((i as f32).to_le_bytes());
+    }
+    data
+}
+
+// answers every query without a challenge, splitting large replies
+async fn upstream(socket: UdpSocket) {
+    let info = info_reply();
+    let player = player_reply();
+    let mut buf = [0u8; 1400];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        if len < 5 {
+            continue;
+        }
+
+        let reply = match buf[4] {
+            0x54 => &info,
+            0x55 => &player,
+            _ => continue,
+        };
+
+        if reply.len() <= 1400 {
+            let _ = socket.send_to(reply, addr).await;
+            continue;
+        }
+
+        let chunks: Vec<&[u8]> = reply.chunks(1248).collect();
+        for (number, chunk) in chunks.iter().enumerate() {
+            let mut packet = (-2i32).to_le_bytes().to_vec();
+            packet.extend(1234i32.to_le_bytes());
+            packet.push(chunks.len() as u8);
+            packet.push(number as u8);
+            packet.extend(1248i16.to_le_bytes());
+            packet.extend(*chunk);
+            let _ = socket.send_to(&packet, addr).await;
+        }
+    }
+}
+
+async fn client(server: SocketAddr, query: Query, received: Arc<AtomicU64>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server).await.unwrap();
+
+    let (request, datagrams) = match query {
+        Query::Info => {
+            let mut request = vec![0xFF, 0xFF, 0xFF, 0xFF, 0x54];
+            request.extend(cstr("Source Engine Query"));
+            (request, 1)
+        }
+        Query::Player => (
+            vec![0xFF, 0xFF, 0xFF, 0xFF, 0x55, 0xFF, 0xFF, 0xFF, 0xFF],
+            3,
+        ),
+    };
+    let mut buf = [0u8; 1500];
+
+    loop {
+        if socket.send(&request).await.is_err() {
+            continue;
+        }
+
+        for _ in 0..datagrams {
+            match tokio::time::timeout(time::Duration::from_millis(200), socket.recv(&mut buf))
+                .await
+            {
+                Ok(Ok(_)) => {
+                    received.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let upstream_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream_socket.local_addr().unwrap();
+    tokio::spawn(upstream(upstream_socket));
+
+    // grab a free port for the cacher
+    let bind = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut config = serde_json::json!({
+        "name": "bench",
+        "host": upstream_addr.to_string(),
+        "bind": bind.to_string(),
+        "cache": { "infoTtl": 3600, "playerTtl": 3600 },
+        "challenge": { "info": "never", "player": "never" },
+        "amplificationRatio": 1000.0,
+    });
+    let overrides: serde_json::Value = serde_json::from_str(&args.server).unwrap();
+    if let (Some(config), Some(overrides)) = (config.as_object_mut(), overrides.as_object()) {
+        for (key, value) in overrides {
+            config.insert(key.clone(), value.clone());
+        }
+    }
+    let config: ServerConfig = serde_json::from_value(config).unwrap();
+
+    let server = Arc::new(SteamQueryCacheServer::new(config).await.unwrap());
+    server.start();
+    tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+    let received = Arc::new(AtomicU64::new(0));
+    let mut clients = JoinSet::new();
+    for _ in 0..args.clients {
+        clients.spawn(client(bind, args.query, received.clone()));
+    }
+
+    // let the cache fill before measuring
+    tokio::time::sleep(time::Duration::from_secs(1)).await;
+    let start_count = received.load(Ordering::Relaxed);
+    let start = time::Instant::now();
+    tokio::time::sleep(time::Duration::from_secs(args.seconds)).await;
+    let count = received.load(Ordering::Relaxed) - start_count;
+
+    println!(
+        "{:?}: {} packets in {:.1}s, {:.0} packets/s",
+        args.query,
+        count,
+        start.elapsed().as_secs_f64(),
+        count as f64 / start.elapsed().as_secs_f64()
+    );
+}
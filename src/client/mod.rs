@@ -1,32 +1,113 @@
 pub mod packets;
 
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
 use tokio::net::{ToSocketAddrs, UdpSocket};
 
 use packets::a2s_info::A2SInfo;
 
+use crate::metrics::{Histogram, LATENCY_BUCKETS};
+
 use self::packets::{
     a2s_info_reply::A2SInfoReply, a2s_player::A2SPlayer, a2s_player_reply::A2SPlayerReply,
-    a2s_rules::A2SRules, a2s_rules_reply::A2SRulesReply, QueryHeader, SourceQueryRequest,
-    SourceQueryResponse, SOURCE_PACKET_HEADER, SOURCE_SIMPLE_PACKET_MAX_SIZE,
+    a2s_rules::A2SRules, a2s_rules_reply::A2SRulesReply, gs_info_reply::GoldSourceInfoReply,
+    split_packet::SplitPacket, QueryHeader, QueryProtocol, SourceQueryRequest, SourceQueryResponse,
+    SOURCE_PACKET_HEADER, SOURCE_SIMPLE_PACKET_MAX_SIZE, SOURCE_SPLIT_PACKET_HEADER,
 };
 
+pub const QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+pub const MAX_IDLE_SOCKETS: usize = 8;
+
 #[derive(Debug)]
 pub struct SteamQueryClient {
-    socket: UdpSocket,
+    addr: SocketAddr,
+    protocol: QueryProtocol,
+    // every exchange takes a socket of its own, so replies cannot end up with
+    // another caller and a query that is never answered holds up no other one
+    idle: std::sync::Mutex<Vec<UdpSocket>>,
+    // when queries started failing, cleared by the next successful one
+    failing_since: std::sync::Mutex<Option<std::time::Instant>>,
+    latency: Arc<Histogram>,
+    timeouts: AtomicU64,
+    errors: AtomicU64,
 }
 
 impl SteamQueryClient {
-    pub async fn new<T>(addr: T) -> std::io::Result<Self>
+    pub async fn new<T>(addr: T, protocol: QueryProtocol) -> std::io::Result<Self>
     where
         T: ToSocketAddrs,
     {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let addr: SocketAddr = match tokio::net::lookup_host(addr).await?.next() {
+            Some(addr) => addr,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Could not resolve upstream address",
+                ))
+            }
+        };
+        let socket = Self::connect(addr).await?;
+
+        Ok(Self {
+            addr,
+            protocol,
+            idle: std::sync::Mutex::new(vec![socket]),
+            failing_since: std::sync::Mutex::new(None),
+            latency: Arc::new(Histogram::new(&LATENCY_BUCKETS)),
+            timeouts: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    async fn connect(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0").await?,
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0").await?,
+        };
         socket.connect(addr).await?;
 
-        Ok(Self { socket })
+        Ok(socket)
     }
 
-    async fn send_packet<T: SourceQueryRequest>(&self, packet: T) -> std::io::Result<()> {
+    async fn checkout(&self) -> std::io::Result<UdpSocket> {
+        let idle = self.idle.lock().unwrap().pop();
+        match idle {
+            Some(socket) => {
+                // discards replies that arrived after an earlier exchange finished,
+                // e.g. the second info reply of servers sending two
+                let mut buf = [0u8; SOURCE_SIMPLE_PACKET_MAX_SIZE];
+                while let Ok(len) = socket.try_recv(&mut buf) {
+                    log::debug!("Discarding {} stale bytes from upstream", len);
+                }
+                Ok(socket)
+            }
+            None => Self::connect(self.addr).await,
+        }
+    }
+
+    // sockets of failed exchanges are dropped instead, so late replies go with them
+    fn checkin(&self, socket: UdpSocket) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_SOCKETS {
+            idle.push(socket);
+        }
+    }
+
+    async fn send_packet<T: SourceQueryRequest>(
+        &self,
+        socket: &UdpSocket,
+        packet: T,
+    ) -> std::io::Result<()> {
         log::trace!("sending packet: {:?}", packet);
         let mut bytes: Vec<u8> = packet.into();
         i32::to_le_bytes(SOURCE_PACKET_HEADER)
@@ -34,38 +115,167 @@ impl SteamQueryClient {
             .for_each(|b| bytes.insert(0, *b));
 
         log::trace!("sending packet bytes: {:?}", bytes);
-        self.socket.send(&bytes).await?;
+        socket.send(&bytes).await?;
 
         Ok(())
     }
 
-    async fn recv_packet_bytes(&self) -> std::io::Result<Vec<u8>> {
+    async fn recv_datagram(
+        &self,
+        socket: &UdpSocket,
+        deadline: tokio::time::Instant,
+    ) -> std::io::Result<Vec<u8>> {
         let mut buf: Vec<u8> = Vec::with_capacity(SOURCE_SIMPLE_PACKET_MAX_SIZE);
 
         tokio::select! {
-            _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {
+            _ = tokio::time::sleep_until(deadline) => {
                 return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"));
             },
-            result = self.socket.recv_buf(&mut buf) => {
+            result = socket.recv_buf(&mut buf) => {
                 result?;
             }
         }
         log::trace!("received packet bytes: {:?}", buf);
 
+        if buf.len() < 4 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid packet size: {}", buf.len()),
+            ));
+        }
+
         Ok(buf)
     }
 
+    async fn recv_packet_bytes(&self, socket: &UdpSocket) -> std::io::Result<Vec<u8>> {
+        let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+
+        let mut packets: Vec<SplitPacket> = Vec::new();
+
+        loop {
+            let buf = self.recv_datagram(socket, deadline).await?;
+
+            let header: i32 = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            if header != SOURCE_SPLIT_PACKET_HEADER {
+                return Ok(buf);
+            }
+
+            let packet = SplitPacket::parse(&buf[4..], self.protocol)?;
+            log::trace!(
+                "Received split packet {}/{} (id {})",
+                packet.number + 1,
+                packet.total,
+                packet.id
+            );
+
+            if let Some(first) = packets.first() {
+                if first.id != packet.id {
+                    log::warn!(
+                        "Discarding split packet with unexpected id {} (expected {})",
+                        packet.id,
+                        first.id
+                    );
+                    continue;
+                }
+            }
+            if packets.iter().any(|p| p.number == packet.number) {
+                continue;
+            }
+
+            let total = packet.total as usize;
+            packets.push(packet);
+
+            if packets.len() == total {
+                let buf = SplitPacket::reassemble(packets)?;
+                log::trace!("reassembled packet bytes: {:?}", buf);
+
+                if buf.len() < 4 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Invalid packet size: {}", buf.len()),
+                    ));
+                }
+
+                return Ok(buf);
+            }
+        }
+    }
+
+    /// How long queries to the upstream have been failing, if they are.
+    pub fn failing_for(&self) -> Option<std::time::Duration> {
+        self.failing_since
+            .lock()
+            .unwrap()
+            .map(|failing_since| failing_since.elapsed())
+    }
+
+    pub fn latency(&self) -> Arc<Histogram> {
+        self.latency.clone()
+    }
+
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    fn record_result<T>(&self, result: &std::io::Result<T>, started: std::time::Instant) {
+        let mut failing_since = self.failing_since.lock().unwrap();
+        match result {
+            Ok(_) => {
+                self.latency.observe(started.elapsed());
+                failing_since.take();
+            }
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::TimedOut {
+                    self.timeouts.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                }
+                failing_since.get_or_insert_with(std::time::Instant::now);
+            }
+        }
+    }
+
     pub async fn query<T: SourceQueryRequest, U: SourceQueryResponse>(
         &self,
+        packet: T,
+    ) -> std::io::Result<U>
+    where
+        for<'a> <U as TryFrom<&'a [u8]>>::Error: std::fmt::Display,
+    {
+        let socket = self.checkout().await?;
+        let started = std::time::Instant::now();
+        let result = self.exchange_query(&socket, packet).await;
+        self.record_result(&result, started);
+        if result.is_ok() {
+            self.checkin(socket);
+        }
+
+        result
+    }
+
+    async fn exchange_query<T: SourceQueryRequest, U: SourceQueryResponse>(
+        &self,
+        socket: &UdpSocket,
         mut packet: T,
     ) -> std::io::Result<U>
     where
         for<'a> <U as TryFrom<&'a [u8]>>::Error: std::fmt::Display,
     {
         loop {
-            self.send_packet(packet.clone()).await?;
+            self.send_packet(socket, packet.clone()).await?;
 
-            let mut packet_bytes: Vec<u8> = self.recv_packet_bytes().await?;
+            let mut packet_bytes: Vec<u8> = self.recv_packet_bytes(socket).await?;
+            // only the 4 byte packet header is guaranteed, the query header follows it
+            if packet_bytes.len() < 5 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid packet size: {}", packet_bytes.len()),
+                ));
+            }
 
             let header: i32 = i32::from_le_bytes([
                 packet_bytes[0],
@@ -96,6 +306,12 @@ impl SteamQueryClient {
 
             if header == QueryHeader::S2CChallenge {
                 log::trace!("Received challenge packet");
+                if packet_bytes.len() < 5 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Invalid challenge packet size",
+                    ));
+                }
                 let challenge: i32 = i32::from_le_bytes([
                     packet_bytes[1],
                     packet_bytes[2],
@@ -108,7 +324,7 @@ impl SteamQueryClient {
                 continue;
             }
 
-            if header != U::packet_header() {
+            if !U::accepts(header) {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     format!("Invalid packet header: {:?}", header),
@@ -137,6 +353,13 @@ impl SteamQueryClient {
         self.query::<A2SInfo, A2SInfoReply>(packet).await
     }
 
+    #[allow(dead_code)]
+    pub async fn gs_info(&self) -> std::io::Result<GoldSourceInfoReply> {
+        let packet: A2SInfo = A2SInfo::new();
+
+        self.query::<A2SInfo, GoldSourceInfoReply>(packet).await
+    }
+
     #[allow(dead_code)]
     pub async fn a2s_player(&self) -> std::io::Result<A2SPlayerReply> {
         let packet: A2SPlayer = A2SPlayer::new();
@@ -151,21 +374,25 @@ impl SteamQueryClient {
         self.query::<A2SRules, A2SRulesReply>(packet).await
     }
 
+    // clients may send anything here, so unanswered requests say nothing about
+    // the upstream's health and are not recorded
     pub async fn proxy_request(&self, request: Vec<u8>) -> std::io::Result<Vec<u8>> {
-        self.socket.send(&request).await?;
+        let socket = self.checkout().await?;
+        let result = self.exchange_proxy_request(&socket, request).await;
+        if result.is_ok() {
+            self.checkin(socket);
+        }
 
-        let mut buf: Vec<u8> = Vec::with_capacity(SOURCE_SIMPLE_PACKET_MAX_SIZE);
+        result
+    }
 
-        tokio::select! {
-            _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"));
-            },
-            result = self.socket.recv_buf(&mut buf) => {
-                result?;
-            }
-        }
-        log::trace!("received packet bytes: {:?}", buf);
+    async fn exchange_proxy_request(
+        &self,
+        socket: &UdpSocket,
+        request: Vec<u8>,
+    ) -> std::io::Result<Vec<u8>> {
+        socket.send(&request).await?;
 
-        Ok(buf)
+        self.recv_packet_bytes(socket).await
     }
 }
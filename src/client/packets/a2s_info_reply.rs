@@ -1,7 +1,11 @@
+use serde::Serialize;
+
 use super::{QueryHeader, SourceQueryResponse};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct A2SInfoReply {
+    #[serde(skip)]
     pub header: QueryHeader,
     pub protocol: u8,
     pub name: String,
@@ -95,7 +99,7 @@ impl TryFrom<&[u8]> for A2SInfoReply {
     type Error = std::io::Error;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        let header = match QueryHeader::try_from(data[0] as u8) {
+        let header = match QueryHeader::try_from(data[0]) {
             Ok(header) => header,
             Err(_) => {
                 return Err(std::io::Error::new(
@@ -1,8 +1,12 @@
+use serde::Serialize;
+
 use super::{QueryHeader, SourceQueryResponse};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[repr(C)]
 pub struct A2SPlayerReply {
+    #[serde(skip)]
     pub header: QueryHeader,
     pub num_players: u8,
     pub players: Vec<A2SPlayerInfo>,
@@ -55,28 +59,12 @@ impl TryFrom<&[u8]> for A2SPlayerReply {
             ));
         }
 
-        let num_players = match u8::try_from(data[0]) {
-            Ok(players) => players,
-            Err(_) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid player count",
-                ))
-            }
-        };
+        let num_players = data[0];
         data = &data[1..];
 
         let mut players = Vec::with_capacity(num_players as usize);
         for _ in 0..num_players {
-            let index = match u8::try_from(data[0]) {
-                Ok(index) => index,
-                Err(_) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Invalid player index",
-                    ))
-                }
-            };
+            let index = data[0];
             data = &data[1..];
 
             let name =
@@ -129,7 +117,8 @@ impl TryFrom<&[u8]> for A2SPlayerReply {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[repr(C)]
 pub struct A2SPlayerInfo {
     pub index: u8,
@@ -1,15 +1,20 @@
+use serde::Serialize;
+
 use super::{QueryHeader, SourceQueryResponse};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[repr(C)]
 pub struct A2SRule {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[repr(C)]
 pub struct A2SRulesReply {
+    #[serde(skip)]
     pub header: QueryHeader,
     pub num_rules: i16,
     pub rules: Vec<A2SRule>,
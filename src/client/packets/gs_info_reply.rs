@@ -0,0 +1,342 @@
+use serde::Serialize;
+
+use super::{a2s_info_reply::A2SInfoReply, QueryHeader, SourceQueryResponse};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GSModInfo {
+    pub link: String,
+    pub download_link: String,
+    pub version: i32,
+    pub size: i32,
+    pub mod_type: u8,
+    pub dll: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GSInfoReply {
+    #[serde(skip)]
+    pub header: QueryHeader,
+    pub address: String,
+    pub name: String,
+    pub map: String,
+    pub folder: String,
+    pub game: String,
+    pub players: u8,
+    pub max_players: u8,
+    pub protocol: u8,
+    pub server_type: u8,
+    pub environment: u8,
+    pub visibility: u8,
+    pub mod_info: Option<GSModInfo>,
+    pub vac: u8,
+    pub bots: u8,
+}
+
+impl SourceQueryResponse for GSInfoReply {
+    fn packet_header() -> QueryHeader {
+        QueryHeader::GSInfo
+    }
+}
+
+// current HLDS builds answer with the Source reply instead of, or in addition
+// to, the obsolete one, either is served to clients as it was received
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum GoldSourceInfoReply {
+    Obsolete(GSInfoReply),
+    Source(A2SInfoReply),
+}
+
+impl SourceQueryResponse for GoldSourceInfoReply {
+    fn packet_header() -> QueryHeader {
+        QueryHeader::GSInfo
+    }
+
+    fn accepts(header: QueryHeader) -> bool {
+        header == QueryHeader::GSInfo || header == QueryHeader::A2SInfoReply
+    }
+}
+
+impl Into<Vec<u8>> for GoldSourceInfoReply {
+    fn into(self) -> Vec<u8> {
+        match self {
+            Self::Obsolete(info) => info.into(),
+            Self::Source(info) => info.into(),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for GoldSourceInfoReply {
+    type Error = std::io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.first().map(|&header| QueryHeader::try_from(header)) {
+            Some(Ok(QueryHeader::A2SInfoReply)) => A2SInfoReply::try_from(value).map(Self::Source),
+            _ => GSInfoReply::try_from(value).map(Self::Obsolete),
+        }
+    }
+}
+
+impl Into<Vec<u8>> for GSInfoReply {
+    fn into(self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(Self::SIZE);
+
+        let header: u8 = self.header.into();
+        data.push(header);
+        data.extend(self.address.as_bytes());
+        data.push(0x00);
+        data.extend(self.name.as_bytes());
+        data.push(0x00);
+        data.extend(self.map.as_bytes());
+        data.push(0x00);
+        data.extend(self.folder.as_bytes());
+        data.push(0x00);
+        data.extend(self.game.as_bytes());
+        data.push(0x00);
+        data.push(self.players);
+        data.push(self.max_players);
+        data.push(self.protocol);
+        data.push(self.server_type);
+        data.push(self.environment);
+        data.push(self.visibility);
+
+        match self.mod_info {
+            Some(mod_info) => {
+                data.push(0x01);
+                data.extend(mod_info.link.as_bytes());
+                data.push(0x00);
+                data.extend(mod_info.download_link.as_bytes());
+                data.push(0x00);
+                data.push(0x00);
+                data.extend(mod_info.version.to_le_bytes().iter());
+                data.extend(mod_info.size.to_le_bytes().iter());
+                data.push(mod_info.mod_type);
+                data.push(mod_info.dll);
+            }
+            None => data.push(0x00),
+        }
+
+        data.push(self.vac);
+        data.push(self.bots);
+
+        data
+    }
+}
+
+fn read_string(data: &mut &[u8], field: &str) -> Result<String, std::io::Error> {
+    let end = match data.iter().position(|&c| c == 0) {
+        Some(end) => end,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unterminated {}", field),
+            ))
+        }
+    };
+
+    let value = match String::from_utf8(data[..end].to_vec()) {
+        Ok(value) => value,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid {}", field),
+            ))
+        }
+    };
+    *data = &data[end + 1..];
+
+    Ok(value)
+}
+
+fn read_bytes<const N: usize>(data: &mut &[u8], field: &str) -> Result<[u8; N], std::io::Error> {
+    if data.len() < N {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Missing {}", field),
+        ));
+    }
+
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(&data[..N]);
+    *data = &data[N..];
+
+    Ok(bytes)
+}
+
+impl TryFrom<&[u8]> for GSInfoReply {
+    type Error = std::io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let mut data = value;
+
+        let [header] = read_bytes::<1>(&mut data, "header")?;
+        let header = match QueryHeader::try_from(header) {
+            Ok(header) => header,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid query header",
+                ))
+            }
+        };
+        if header != QueryHeader::GSInfo {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid header for GSInfoReply: {:?}", header),
+            ));
+        }
+
+        let address = read_string(&mut data, "address")?;
+        let name = read_string(&mut data, "name")?;
+        let map = read_string(&mut data, "map")?;
+        let folder = read_string(&mut data, "folder")?;
+        let game = read_string(&mut data, "game")?;
+
+        let [players, max_players, protocol, server_type, environment, visibility, is_mod] =
+            read_bytes::<7>(&mut data, "server details")?;
+
+        let mod_info = if is_mod == 1 {
+            let link = read_string(&mut data, "mod link")?;
+            let download_link = read_string(&mut data, "mod download link")?;
+            read_bytes::<1>(&mut data, "mod padding")?;
+            let version = i32::from_le_bytes(read_bytes::<4>(&mut data, "mod version")?);
+            let size = i32::from_le_bytes(read_bytes::<4>(&mut data, "mod size")?);
+            let [mod_type, dll] = read_bytes::<2>(&mut data, "mod type")?;
+
+            Some(GSModInfo {
+                link,
+                download_link,
+                version,
+                size,
+                mod_type,
+                dll,
+            })
+        } else {
+            None
+        };
+
+        let [vac, bots] = read_bytes::<2>(&mut data, "vac and bots")?;
+
+        Ok(Self {
+            header,
+            address,
+            name,
+            map,
+            folder,
+            game,
+            players,
+            max_players,
+            protocol,
+            server_type,
+            environment,
+            visibility,
+            mod_info,
+            vac,
+            bots,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(mod_info: Option<GSModInfo>) -> GSInfoReply {
+        GSInfoReply {
+            header: QueryHeader::GSInfo,
+            address: "127.0.0.1:27015".to_string(),
+            name: "Half-Life".to_string(),
+            map: "crossfire".to_string(),
+            folder: "valve".to_string(),
+            game: "Half-Life".to_string(),
+            players: 3,
+            max_players: 16,
+            protocol: 47,
+            server_type: b'd',
+            environment: b'l',
+            visibility: 0,
+            mod_info,
+            vac: 1,
+            bots: 1,
+        }
+    }
+
+    #[test]
+    fn parses_obsolete_reply() {
+        let bytes: Vec<u8> = reply(None).into();
+
+        assert_eq!(
+            GSInfoReply::try_from(bytes.as_slice()).unwrap(),
+            reply(None)
+        );
+    }
+
+    #[test]
+    fn parses_obsolete_reply_with_mod_info() {
+        let mod_info = GSModInfo {
+            link: "http://example.com".to_string(),
+            download_link: "http://example.com/dl".to_string(),
+            version: 2,
+            size: 1024,
+            mod_type: 1,
+            dll: 1,
+        };
+        let bytes: Vec<u8> = reply(Some(mod_info.clone())).into();
+
+        let parsed = GSInfoReply::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed.mod_info, Some(mod_info));
+        assert_eq!((parsed.vac, parsed.bots), (1, 1));
+    }
+
+    #[test]
+    fn keeps_the_format_of_the_reply() {
+        let info = A2SInfoReply {
+            header: QueryHeader::A2SInfoReply,
+            protocol: 48,
+            name: "Half-Life".to_string(),
+            map: "crossfire".to_string(),
+            folder: "valve".to_string(),
+            game: "Half-Life".to_string(),
+            id: 70,
+            players: 3,
+            max_players: 16,
+            bots: 1,
+            server_type: b'd',
+            environment: b'l',
+            visibility: 0,
+            vac: 1,
+            version: "1.1.2.7".to_string(),
+            edf: 0xB1,
+            port: Some(27015),
+            steam_id: Some(90071992547409920),
+            source_tv_port: None,
+            source_tv_name: None,
+            keywords: Some("secure".to_string()),
+            game_id: Some(70),
+        };
+        let bytes: Vec<u8> = info.clone().into();
+
+        assert!(GoldSourceInfoReply::accepts(QueryHeader::A2SInfoReply));
+        assert!(!GSInfoReply::accepts(QueryHeader::A2SInfoReply));
+        let parsed = GoldSourceInfoReply::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed, GoldSourceInfoReply::Source(info));
+        assert_eq!(Into::<Vec<u8>>::into(parsed), bytes);
+
+        let bytes: Vec<u8> = reply(None).into();
+        let parsed = GoldSourceInfoReply::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed, GoldSourceInfoReply::Obsolete(reply(None)));
+        assert_eq!(Into::<Vec<u8>>::into(parsed), bytes);
+    }
+
+    #[test]
+    fn rejects_other_headers_and_truncated_replies() {
+        let bytes: Vec<u8> = reply(None).into();
+
+        assert!(!GoldSourceInfoReply::accepts(QueryHeader::A2SPlayerReply));
+        assert!(GSInfoReply::try_from(&[0x44u8, 0][..]).is_err());
+        assert!(GSInfoReply::try_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(GoldSourceInfoReply::try_from(&[][..]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+#![allow(clippy::from_over_into)]
 
 pub mod a2s_info;
 pub mod a2s_info_reply;
@@ -6,17 +7,36 @@ pub mod a2s_player;
 pub mod a2s_player_reply;
 pub mod a2s_rules;
 pub mod a2s_rules_reply;
+pub mod gs_info_reply;
 pub mod s2c_challenge;
+pub mod split_packet;
 
 use std::fmt::Debug;
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
 
 pub const SOURCE_PACKET_HEADER: i32 = -1;
+pub const SOURCE_SPLIT_PACKET_HEADER: i32 = -2;
 pub const SOURCE_SIMPLE_PACKET_MAX_SIZE: usize = 1400;
+pub const SOURCE_SPLIT_PACKET_PAYLOAD_SIZE: usize = 1248;
+pub const GOLDSOURCE_SPLIT_PACKET_PAYLOAD_SIZE: usize = 1391;
+// the part count is a byte in Source and a nibble in GoldSource split packets
+pub const SOURCE_MAX_SPLIT_PACKETS: usize = 255;
+pub const GOLDSOURCE_MAX_SPLIT_PACKETS: usize = 15;
+// the largest response that can be split again for clients
+pub const MAX_RESPONSE_SIZE: usize = SOURCE_MAX_SPLIT_PACKETS * SOURCE_SPLIT_PACKET_PAYLOAD_SIZE;
 
 pub type SourceChallenge = i32;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QueryProtocol {
+    #[default]
+    Source,
+    GoldSource,
+}
+
 #[derive(Debug, Clone, Copy, TryFromPrimitive, IntoPrimitive, PartialEq, Eq)]
 #[repr(u8)]
 pub enum QueryHeader {
@@ -30,6 +50,7 @@ pub enum QueryHeader {
     A2SInfoReply = 0x49,
     A2APing = 0x69,
     A2APingReply = 0x6A,
+    // obsolete GoldSource A2S_INFO reply
     GSInfo = 0x6D,
     GSInfoReply = 0x6E,
 }
@@ -39,6 +60,11 @@ pub trait SourceQueryResponse:
 {
     fn packet_header() -> QueryHeader;
 
+    /// Whether a reply with `header` can be parsed as this response.
+    fn accepts(header: QueryHeader) -> bool {
+        header == Self::packet_header()
+    }
+
     const SIZE: usize = std::mem::size_of::<Self>();
 }
 
@@ -0,0 +1,437 @@
+use std::io::Read;
+
+use super::{
+    QueryProtocol, GOLDSOURCE_MAX_SPLIT_PACKETS, GOLDSOURCE_SPLIT_PACKET_PAYLOAD_SIZE,
+    MAX_RESPONSE_SIZE, SOURCE_MAX_SPLIT_PACKETS, SOURCE_SPLIT_PACKET_PAYLOAD_SIZE,
+};
+
+const COMPRESSED_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPacket {
+    pub id: i32,
+    pub total: u8,
+    pub number: u8,
+    pub size: i16,
+    pub decompressed_size: Option<i32>,
+    pub crc32: Option<u32>,
+    pub payload: Vec<u8>,
+}
+
+impl SplitPacket {
+    pub fn is_compressed(&self) -> bool {
+        self.id as u32 & COMPRESSED_FLAG != 0
+    }
+
+    /// Splits a full simple packet (including its -1 header) into uncompressed
+    /// split packets that can be sent to a client one after another.
+    pub fn split(
+        id: i32,
+        data: &[u8],
+        protocol: QueryProtocol,
+    ) -> Result<Vec<Self>, std::io::Error> {
+        let id = (id as u32 & !COMPRESSED_FLAG) as i32;
+        let (chunk_size, max_packets) = match protocol {
+            QueryProtocol::Source => (SOURCE_SPLIT_PACKET_PAYLOAD_SIZE, SOURCE_MAX_SPLIT_PACKETS),
+            QueryProtocol::GoldSource => (
+                GOLDSOURCE_SPLIT_PACKET_PAYLOAD_SIZE,
+                GOLDSOURCE_MAX_SPLIT_PACKETS,
+            ),
+        };
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+        if chunks.len() > max_packets {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Response of {} bytes needs {} split packets, at most {} are possible",
+                    data.len(),
+                    chunks.len(),
+                    max_packets
+                ),
+            ));
+        }
+        let total = chunks.len() as u8;
+
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(number, chunk)| Self {
+                id,
+                total,
+                number: number as u8,
+                size: chunk_size as i16,
+                decompressed_size: None,
+                crc32: None,
+                payload: chunk.to_vec(),
+            })
+            .collect())
+    }
+
+    /// Joins all parts of a split response back into a simple packet, which
+    /// still starts with the -1 header.
+    pub fn reassemble(mut packets: Vec<Self>) -> Result<Vec<u8>, std::io::Error> {
+        packets.sort_by_key(|packet| packet.number);
+
+        let first = match packets.first() {
+            Some(first) => first,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "No split packets to reassemble",
+                ))
+            }
+        };
+        if packets.len() != first.total as usize
+            || packets
+                .iter()
+                .enumerate()
+                .any(|(i, packet)| packet.number as usize != i || packet.id != first.id)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Incomplete split packet sequence",
+            ));
+        }
+
+        let compressed = first.is_compressed();
+        let decompressed_size = first.decompressed_size;
+        let crc32 = first.crc32;
+
+        let data: Vec<u8> = packets.into_iter().flat_map(|p| p.payload).collect();
+        if !compressed {
+            return Ok(data);
+        }
+
+        // both the announced and the actual size are capped, so neither a forged
+        // size nor a bzip2 bomb can exhaust memory
+        let capacity = decompressed_size.unwrap_or_default().max(0) as usize;
+        if capacity > MAX_RESPONSE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Decompressed size {} is too large", capacity),
+            ));
+        }
+
+        let mut decompressed: Vec<u8> = Vec::with_capacity(capacity);
+        if let Err(e) = bzip2::read::BzDecoder::new(data.as_slice())
+            .take(MAX_RESPONSE_SIZE as u64 + 1)
+            .read_to_end(&mut decompressed)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to decompress split packet: {}", e),
+            ));
+        }
+        if decompressed.len() > MAX_RESPONSE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Decompressed split packet is too large",
+            ));
+        }
+
+        if let Some(size) = decompressed_size {
+            if decompressed.len() != size as usize {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid decompressed size: expected {}, got {}",
+                        size,
+                        decompressed.len()
+                    ),
+                ));
+            }
+        }
+
+        if let Some(crc32) = crc32 {
+            if crc32fast::hash(&decompressed) != crc32 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid split packet checksum",
+                ));
+            }
+        }
+
+        Ok(decompressed)
+    }
+}
+
+impl SplitPacket {
+    pub fn parse(value: &[u8], protocol: QueryProtocol) -> Result<Self, std::io::Error> {
+        match protocol {
+            QueryProtocol::Source => Self::try_from(value),
+            QueryProtocol::GoldSource => Self::parse_goldsource(value),
+        }
+    }
+
+    pub fn encode(self, protocol: QueryProtocol) -> Vec<u8> {
+        match protocol {
+            QueryProtocol::Source => self.into(),
+            QueryProtocol::GoldSource => self.encode_goldsource(),
+        }
+    }
+
+    // GoldSource packs the packet number into the upper and the total into the
+    // lower nibble of a single byte and has neither a size nor compression.
+    fn parse_goldsource(value: &[u8]) -> Result<Self, std::io::Error> {
+        if value.len() < 5 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid split packet size",
+            ));
+        }
+
+        let id = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+        let number = value[4] >> 4;
+        let total = value[4] & 0x0F;
+
+        if total == 0 || number >= total {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid split packet number: {}/{}", number, total),
+            ));
+        }
+
+        Ok(Self {
+            id: (id as u32 & !COMPRESSED_FLAG) as i32,
+            total,
+            number,
+            size: 0,
+            decompressed_size: None,
+            crc32: None,
+            payload: value[5..].to_vec(),
+        })
+    }
+
+    fn encode_goldsource(self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(4 + 1 + self.payload.len());
+
+        data.extend(self.id.to_le_bytes().iter());
+        data.push((self.number << 4) | (self.total & 0x0F));
+        data.extend(self.payload);
+
+        data
+    }
+}
+
+impl Into<Vec<u8>> for SplitPacket {
+    fn into(self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(4 + 1 + 1 + 2 + 8 + self.payload.len());
+
+        data.extend(self.id.to_le_bytes().iter());
+        data.push(self.total);
+        data.push(self.number);
+        data.extend(self.size.to_le_bytes().iter());
+
+        if let Some(decompressed_size) = self.decompressed_size {
+            data.extend(decompressed_size.to_le_bytes().iter());
+        }
+
+        if let Some(crc32) = self.crc32 {
+            data.extend(crc32.to_le_bytes().iter());
+        }
+
+        data.extend(self.payload);
+
+        data
+    }
+}
+
+impl TryFrom<&[u8]> for SplitPacket {
+    type Error = std::io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() < 8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid split packet size",
+            ));
+        }
+
+        let id = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+        let total = value[4];
+        let number = value[5];
+        let size = i16::from_le_bytes([value[6], value[7]]);
+        let mut data = &value[8..];
+
+        if total == 0 || number >= total {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid split packet number: {}/{}", number, total),
+            ));
+        }
+
+        let mut decompressed_size = None;
+        let mut crc32 = None;
+
+        if number == 0 && id as u32 & COMPRESSED_FLAG != 0 {
+            if data.len() < 8 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid compressed split packet size",
+                ));
+            }
+
+            decompressed_size = Some(i32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+            crc32 = Some(u32::from_le_bytes([data[4], data[5], data[6], data[7]]));
+            data = &data[8..];
+        }
+
+        Ok(Self {
+            id,
+            total,
+            number,
+            size,
+            decompressed_size,
+            crc32,
+            payload: data.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn source_packet(id: i32, total: u8, number: u8, payload: &[u8]) -> Vec<u8> {
+        SplitPacket {
+            id,
+            total,
+            number,
+            size: SOURCE_SPLIT_PACKET_PAYLOAD_SIZE as i16,
+            decompressed_size: None,
+            crc32: None,
+            payload: payload.to_vec(),
+        }
+        .into()
+    }
+
+    fn compressed_packets(data: &[u8], crc32: u32) -> Vec<SplitPacket> {
+        let mut compressed: Vec<u8> = Vec::new();
+        bzip2::read::BzEncoder::new(data, bzip2::Compression::best())
+            .read_to_end(&mut compressed)
+            .unwrap();
+
+        let mut first: Vec<u8> = Vec::new();
+        first.extend((data.len() as i32).to_le_bytes());
+        first.extend(crc32.to_le_bytes());
+        first.extend(&compressed);
+
+        let id = (COMPRESSED_FLAG | 7) as i32;
+        vec![SplitPacket::try_from(source_packet(id, 1, 0, &first).as_slice()).unwrap()]
+    }
+
+    #[test]
+    fn reassembles_source_parts_out_of_order() {
+        let packets: Vec<SplitPacket> = [(2, b"!!"), (0, b"ab"), (1, b"cd")]
+            .iter()
+            .map(|(number, payload)| {
+                SplitPacket::parse(
+                    &source_packet(42, 3, *number, *payload),
+                    QueryProtocol::Source,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        assert_eq!(SplitPacket::reassemble(packets).unwrap(), b"abcd!!");
+    }
+
+    #[test]
+    fn rejects_duplicate_and_missing_parts() {
+        let part = |number: u8| {
+            SplitPacket::parse(&source_packet(42, 3, number, b"xx"), QueryProtocol::Source).unwrap()
+        };
+
+        assert!(SplitPacket::reassemble(vec![part(0), part(2)]).is_err());
+        assert!(SplitPacket::reassemble(vec![part(0), part(1), part(1)]).is_err());
+        assert!(SplitPacket::reassemble(vec![part(0), part(0), part(1), part(2)]).is_err());
+        assert!(SplitPacket::reassemble(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_parts_of_different_responses() {
+        let first = SplitPacket::parse(&source_packet(1, 2, 0, b"ab"), QueryProtocol::Source);
+        let second = SplitPacket::parse(&source_packet(2, 2, 1, b"cd"), QueryProtocol::Source);
+
+        assert!(SplitPacket::reassemble(vec![first.unwrap(), second.unwrap()]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_part_numbers() {
+        assert!(SplitPacket::parse(&source_packet(1, 2, 2, b"ab"), QueryProtocol::Source).is_err());
+        assert!(SplitPacket::parse(&source_packet(1, 0, 0, b"ab"), QueryProtocol::Source).is_err());
+        assert!(SplitPacket::parse(&[0; 7], QueryProtocol::Source).is_err());
+    }
+
+    #[test]
+    fn decompresses_bzip2_payloads() {
+        let data = b"\xff\xff\xff\xffEcompressed rules".repeat(8);
+        let packets = compressed_packets(&data, crc32fast::hash(&data));
+
+        assert_eq!(SplitPacket::reassemble(packets).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_bzip2_payloads_with_bad_crc() {
+        let data = b"\xff\xff\xff\xffEcompressed rules".repeat(8);
+        let packets = compressed_packets(&data, crc32fast::hash(&data) ^ 1);
+
+        let e = SplitPacket::reassemble(packets).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parses_goldsource_nibbles() {
+        let packet = SplitPacket::parse(&[9, 0, 0, 0, 0x23, b'x'], QueryProtocol::GoldSource);
+        let packet = packet.unwrap();
+
+        assert_eq!((packet.id, packet.number, packet.total), (9, 2, 3));
+        assert_eq!(packet.payload, b"x");
+        assert!(SplitPacket::parse(&[9, 0, 0, 0, 0x33, b'x'], QueryProtocol::GoldSource).is_err());
+        assert!(SplitPacket::parse(&[9, 0, 0, 0, 0x10], QueryProtocol::GoldSource).is_err());
+    }
+
+    #[test]
+    fn splits_and_encodes_goldsource_round_trip() {
+        let data = vec![7u8; GOLDSOURCE_SPLIT_PACKET_PAYLOAD_SIZE * 2 + 10];
+        let packets: Vec<SplitPacket> = SplitPacket::split(5, &data, QueryProtocol::GoldSource)
+            .unwrap()
+            .into_iter()
+            .rev()
+            .map(|packet| {
+                let encoded = packet.encode(QueryProtocol::GoldSource);
+                SplitPacket::parse(&encoded, QueryProtocol::GoldSource).unwrap()
+            })
+            .collect();
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(SplitPacket::reassemble(packets).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_responses_with_too_many_parts() {
+        let data = vec![7u8; GOLDSOURCE_SPLIT_PACKET_PAYLOAD_SIZE * GOLDSOURCE_MAX_SPLIT_PACKETS];
+        assert!(SplitPacket::split(5, &data, QueryProtocol::GoldSource).is_ok());
+
+        let data = vec![7u8; data.len() + 1];
+        assert!(SplitPacket::split(5, &data, QueryProtocol::GoldSource).is_err());
+        assert!(SplitPacket::split(5, &data, QueryProtocol::Source).is_ok());
+
+        let data = vec![7u8; MAX_RESPONSE_SIZE + 1];
+        assert!(SplitPacket::split(5, &data, QueryProtocol::Source).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_bzip2_payloads() {
+        let data = vec![0u8; MAX_RESPONSE_SIZE + 1];
+        let packets = compressed_packets(&data, crc32fast::hash(&data));
+        assert!(SplitPacket::reassemble(packets).is_err());
+
+        let data = b"\xff\xff\xff\xffEcompressed rules".to_vec();
+        let mut packets = compressed_packets(&data, crc32fast::hash(&data));
+        packets[0].decompressed_size = Some(i32::MAX);
+        assert!(SplitPacket::reassemble(packets).is_err());
+    }
+}
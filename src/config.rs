@@ -1,24 +1,1034 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, Clone)]
+pub use crate::client::packets::QueryProtocol;
+use crate::server::{IpFilter, DEFAULT_BAN_DURATION, DEFAULT_MAX_BAN_DURATION};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CacheMode {
+    #[default]
+    Lazy,
+    Background,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CacheConfig {
+    pub info_ttl: Option<u64>,
+    pub player_ttl: Option<u64>,
+    pub rules_ttl: Option<u64>,
+    pub mode: Option<CacheMode>,
+    pub stale_ttl: Option<u64>,
+    pub stale_name_suffix: Option<String>,
+    pub stale_players: Option<u8>,
+}
+
+impl CacheConfig {
+    pub fn or(&self, defaults: &CacheConfig) -> CacheConfig {
+        CacheConfig {
+            info_ttl: self.info_ttl.or(defaults.info_ttl),
+            player_ttl: self.player_ttl.or(defaults.player_ttl),
+            rules_ttl: self.rules_ttl.or(defaults.rules_ttl),
+            mode: self.mode.or(defaults.mode),
+            stale_ttl: self.stale_ttl.or(defaults.stale_ttl),
+            stale_name_suffix: self
+                .stale_name_suffix
+                .clone()
+                .or(defaults.stale_name_suffix.clone()),
+            stale_players: self.stale_players.or(defaults.stale_players),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // packets per second
+    pub rate: f64,
+    pub burst: u32,
+    pub ipv4_prefix: Option<u8>,
+    pub ipv6_prefix: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BanConfig {
+    pub strikes: Option<u32>,
+    pub strike_window: Option<u64>,
+    pub duration: Option<u64>,
+    pub max_duration: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
+pub enum ChallengePolicy {
+    #[default]
+    Always,
+    Never,
+    UnderLoad,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ChallengeConfig {
+    #[serde(default)]
+    pub info: ChallengePolicy,
+    #[serde(default)]
+    pub player: ChallengePolicy,
+    #[serde(default)]
+    pub rules: ChallengePolicy,
+    // packets per second above which `underLoad` requires a challenge
+    pub load_threshold: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InfoRewriteConfig {
+    // supports {name}, {map}, {players}, {maxPlayers} and {bots} placeholders
+    pub name: Option<String>,
+    #[serde(default)]
+    pub hide_bots: bool,
+    pub visibility: Option<u8>,
+    pub append_keywords: Option<String>,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PlayerNameMode {
+    #[default]
+    Keep,
+    Strip,
+    Hash,
+    Placeholder,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PlayerFilterConfig {
+    #[serde(default)]
+    pub names: PlayerNameMode,
+    // supports an {index} placeholder
+    pub placeholder: Option<String>,
+    #[serde(default)]
+    pub hide_unnamed: bool,
+    pub max_players: Option<u8>,
+    // seconds durations are rounded to
+    pub round_duration: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RulesFilterConfig {
+    // rule name patterns, `*` matches any number and `?` a single character
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub inject: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OfflineConfig {
+    // seconds the upstream has to fail before the offline responses are served
+    pub after: Option<u64>,
+    pub name: Option<String>,
+    pub map: Option<String>,
+    pub folder: Option<String>,
+    pub game: Option<String>,
+    pub app_id: Option<i16>,
+    pub max_players: Option<u8>,
+    // game server address in the obsolete GoldSource reply, defaults to the host
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ServerConfig {
     pub name: String,
     pub host: String,
     pub bind: String,
+    #[serde(default)]
+    pub protocol: QueryProtocol,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub info_rewrite: InfoRewriteConfig,
+    #[serde(default)]
+    pub player_filter: PlayerFilterConfig,
+    #[serde(default)]
+    pub rules_filter: RulesFilterConfig,
+    pub offline: Option<OfflineConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub ban: Option<BanConfig>,
+    #[serde(default)]
+    pub challenge: ChallengeConfig,
+    // maximum response to request size ratio for clients without a valid challenge
+    pub amplification_ratio: Option<f64>,
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+    #[serde(default)]
+    pub denylist: Vec<String>,
+    // number of tasks handling packets concurrently
+    pub workers: Option<usize>,
+    // maximum number of clients whose state is tracked at once
+    pub max_clients: Option<usize>,
+    // number of SO_REUSEPORT sockets bound to the bind address (Linux only)
+    pub sockets: Option<usize>,
+    // maximum number of datagrams read per recvmmsg call (Linux only)
+    pub batch_size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: String,
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Config {
+    // deserialized one by one, so the problems of every server are reported
+    #[serde(skip)]
     pub servers: Vec<ServerConfig>,
     pub log_level: Option<String>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    // serves Prometheus metrics at /metrics and the cached responses at /status
+    pub http: Option<HttpConfig>,
+}
+
+// a single problem found while validating the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl std::fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} problem(s) found", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+// longer durations could overflow the instants they are added to
+pub const MAX_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<ConfigError>,
+}
+
+impl Validator {
+    fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(ConfigError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn at_least_one(&mut self, field: String, value: Option<usize>) {
+        if value == Some(0) {
+            self.error(field, "Must be at least 1");
+        }
+    }
+
+    fn seconds(&mut self, field: String, value: Option<u64>) {
+        if value.is_some_and(|value| value > MAX_SECONDS) {
+            self.error(field, format!("Must be at most {} seconds", MAX_SECONDS));
+        }
+    }
+
+    // only checks what `cache` sets itself, values it inherits are checked where
+    // they are set
+    fn cache(&mut self, field: &str, cache: &CacheConfig, inherited: &CacheConfig) {
+        let effective = cache.or(inherited);
+        let background = effective.mode == Some(CacheMode::Background);
+        let mode_changed = effective.mode.unwrap_or_default() != inherited.mode.unwrap_or_default();
+
+        for (name, own, ttl) in [
+            ("infoTtl", cache.info_ttl, effective.info_ttl),
+            ("playerTtl", cache.player_ttl, effective.player_ttl),
+            ("rulesTtl", cache.rules_ttl, effective.rules_ttl),
+        ] {
+            if own.is_none() && !mode_changed {
+                continue;
+            }
+
+            let field = format!("{}.{}", field, name);
+            if background && ttl == Some(0) {
+                self.error(field, "Must be at least 1 in background mode");
+            } else {
+                self.seconds(field, own);
+            }
+        }
+
+        self.seconds(format!("{}.staleTtl", field), cache.stale_ttl);
+    }
+
+    fn log_level(&mut self, log_level: &str) {
+        // env_logger directives: `level`, `module=level` or `module`, optionally
+        // followed by a `/regex` filter
+        let directives = log_level.split('/').next().unwrap_or_default();
+        for directive in directives.split(',').map(str::trim) {
+            let level = match directive.split_once('=') {
+                Some((_, level)) => level,
+                None if directive.contains("::") => continue,
+                None => directive,
+            };
+            if !level.is_empty() && level.parse::<log::LevelFilter>().is_err() {
+                self.error(
+                    "logLevel",
+                    format!(
+                        "Invalid level {}, expected off, error, warn, info, debug or trace",
+                        level
+                    ),
+                );
+            }
+        }
+    }
+
+    async fn resolve(&mut self, field: String, addr: &str) -> Option<SocketAddr> {
+        match tokio::net::lookup_host(addr)
+            .await
+            .map(|mut addrs| addrs.next())
+        {
+            Ok(Some(addr)) => Some(addr),
+            Ok(None) => {
+                self.error(field, format!("Could not resolve {}", addr));
+                None
+            }
+            Err(e) => {
+                self.error(field, format!("Could not resolve {}: {}", addr, e));
+                None
+            }
+        }
+    }
+
+    // parsed one by one with the server's own filter, so each entry is reported
+    fn ranges(&mut self, field: String, ranges: &[String]) {
+        for (i, range) in ranges.iter().enumerate() {
+            if let Err(e) = IpFilter::new(std::slice::from_ref(range), &[]) {
+                self.error(format!("{}[{}]", field, i), e.to_string());
+            }
+        }
+    }
+
+    async fn server(
+        &mut self,
+        field: &str,
+        server: &ServerConfig,
+        cache: &CacheConfig,
+    ) -> Option<SocketAddr> {
+        if server.name.is_empty() {
+            self.error(format!("{}.name", field), "Must not be empty");
+        }
+
+        self.resolve(format!("{}.host", field), &server.host).await;
+        let bind = self.resolve(format!("{}.bind", field), &server.bind).await;
+
+        self.ranges(format!("{}.allowlist", field), &server.allowlist);
+        self.ranges(format!("{}.denylist", field), &server.denylist);
+
+        if let Some(rate_limit) = &server.rate_limit {
+            if !(rate_limit.rate.is_finite() && rate_limit.rate > 0.0) {
+                self.error(
+                    format!("{}.rateLimit.rate", field),
+                    "Must be a positive number",
+                );
+            }
+            if rate_limit.burst == 0 {
+                self.error(format!("{}.rateLimit.burst", field), "Must be at least 1");
+            }
+            if rate_limit.ipv4_prefix.is_some_and(|prefix| prefix > 32) {
+                self.error(
+                    format!("{}.rateLimit.ipv4Prefix", field),
+                    "Must be at most 32",
+                );
+            }
+            if rate_limit.ipv6_prefix.is_some_and(|prefix| prefix > 128) {
+                self.error(
+                    format!("{}.rateLimit.ipv6Prefix", field),
+                    "Must be at most 128",
+                );
+            }
+        }
+
+        self.cache(&format!("{}.cache", field), &server.cache, cache);
+
+        if let Some(ban) = &server.ban {
+            if ban.strikes == Some(0) {
+                self.error(format!("{}.ban.strikes", field), "Must be at least 1");
+            }
+            self.seconds(format!("{}.ban.strikeWindow", field), ban.strike_window);
+            self.seconds(format!("{}.ban.duration", field), ban.duration);
+            self.seconds(format!("{}.ban.maxDuration", field), ban.max_duration);
+            if ban.duration.unwrap_or(DEFAULT_BAN_DURATION)
+                > ban.max_duration.unwrap_or(DEFAULT_MAX_BAN_DURATION)
+            {
+                self.error(
+                    format!("{}.ban.duration", field),
+                    "Must not be longer than maxDuration",
+                );
+            }
+        }
+
+        if server.challenge.load_threshold == Some(0) {
+            self.error(
+                format!("{}.challenge.loadThreshold", field),
+                "Must be at least 1",
+            );
+        }
+
+        if let Some(offline) = &server.offline {
+            self.seconds(format!("{}.offline.after", field), offline.after);
+            if let Some(address) = &offline.address {
+                if address.parse::<SocketAddr>().is_err() {
+                    self.error(
+                        format!("{}.offline.address", field),
+                        format!(
+                            "Invalid address {}, expected an IP address and port",
+                            address
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Some(ratio) = server.amplification_ratio {
+            // below 1 not even a challenge fits the limit for 9 byte requests
+            if !(ratio.is_finite() && ratio >= 1.0) {
+                self.error(
+                    format!("{}.amplificationRatio", field),
+                    "Must be a number of at least 1",
+                );
+            }
+        }
+
+        self.at_least_one(format!("{}.workers", field), server.workers);
+        self.at_least_one(format!("{}.maxClients", field), server.max_clients);
+        self.at_least_one(format!("{}.sockets", field), server.sockets);
+        self.at_least_one(format!("{}.batchSize", field), server.batch_size);
+
+        bind
+    }
+}
+
+pub const ENV_PREFIX: &str = "SQC_";
+
+// keys holding objects or lists, used to tell where the name of a nested field
+// starts in environment variables, e.g. SQC_SERVERS_0_RATE_LIMIT_RATE
+const ENV_CONTAINERS: [&str; 10] = [
+    "servers",
+    "cache",
+    "infoRewrite",
+    "playerFilter",
+    "rulesFilter",
+    "offline",
+    "rateLimit",
+    "ban",
+    "challenge",
+    "http",
+];
+// keys holding maps whose keys are taken as they are, just lowercased
+const ENV_MAPS: [&str; 1] = ["inject"];
+
+fn camel_case(segments: &[String]) -> String {
+    let mut key = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let segment = segment.to_lowercase();
+        let mut chars = segment.chars();
+        match (i, chars.next()) {
+            (0, _) => key.push_str(&segment),
+            (_, Some(first)) => {
+                key.extend(first.to_uppercase());
+                key.push_str(chars.as_str());
+            }
+            (_, None) => {}
+        }
+    }
+
+    key
+}
+
+// sets the field at the path given by the segments of an environment variable name,
+// creating the objects and list entries on the way, and returns its JSON pointer
+fn set_path(
+    value: &mut serde_json::Value,
+    segments: &[String],
+    new: serde_json::Value,
+) -> Option<String> {
+    let (first, _) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            *value = new;
+            return Some(String::new());
+        }
+    };
+
+    if let Ok(index) = first.parse::<usize>() {
+        if !value.is_array() {
+            *value = serde_json::Value::Array(Vec::new());
+        }
+        let list = value.as_array_mut().unwrap();
+        // entries can only be appended, so a typo cannot allocate a huge list
+        if index > list.len() {
+            return None;
+        }
+        if index == list.len() {
+            list.push(serde_json::Value::Null);
+        }
+        let pointer = set_path(&mut list[index], &segments[1..], new)?;
+        return Some(format!("/{}{}", index, pointer));
+    }
+
+    if !value.is_object() {
+        *value = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let map = value.as_object_mut().unwrap();
+
+    // the longest run of segments naming an existing or known nested key wins,
+    // whatever is left over names the field itself
+    let mut split: Option<(String, usize)> = None;
+    for len in (1..=segments.len()).rev() {
+        let joined = segments[..len].concat().to_lowercase();
+        if let Some(key) = map.keys().find(|key| key.to_lowercase() == joined) {
+            split = Some((key.clone(), len));
+            break;
+        }
+        let key = camel_case(&segments[..len]);
+        if len < segments.len()
+            && (ENV_CONTAINERS.contains(&key.as_str()) || ENV_MAPS.contains(&key.as_str()))
+        {
+            split = Some((key, len));
+            break;
+        }
+    }
+    let (key, len) = split.unwrap_or_else(|| (camel_case(segments), segments.len()));
+
+    let entry = map.entry(key.clone()).or_insert(serde_json::Value::Null);
+    if ENV_MAPS.contains(&key.as_str()) && len < segments.len() {
+        if !entry.is_object() {
+            *entry = serde_json::Value::Object(serde_json::Map::new());
+        }
+        let name = segments[len..].join("_").to_lowercase();
+        let pointer = format!("/{}/{}", pointer_token(&key), pointer_token(&name));
+        entry.as_object_mut().unwrap().insert(name, new);
+        return Some(pointer);
+    }
+
+    let pointer = set_path(entry, &segments[len..], new)?;
+    Some(format!("/{}{}", pointer_token(&key), pointer))
+}
+
+fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+// deserializes a value, retrying with the string form of every environment
+// variable that was parsed as JSON but lands in a string field
+fn deserialize_coerced<T: serde::de::DeserializeOwned>(
+    mut value: serde_json::Value,
+    prefix: &str,
+    env: &HashMap<String, String>,
+) -> Result<T, serde_path_to_error::Error<serde_json::Error>> {
+    loop {
+        let e = match serde_path_to_error::deserialize::<_, T>(&value) {
+            Ok(parsed) => return Ok(parsed),
+            Err(e) => e,
+        };
+
+        let mut pointer = String::new();
+        for segment in e.path().iter() {
+            match segment {
+                serde_path_to_error::Segment::Seq { index } => {
+                    pointer.push_str(&format!("/{}", index))
+                }
+                serde_path_to_error::Segment::Map { key } => {
+                    pointer.push_str(&format!("/{}", pointer_token(key)))
+                }
+                _ => return Err(e),
+            }
+        }
+
+        match (
+            env.get(&format!("{}{}", prefix, pointer)),
+            value.pointer_mut(&pointer),
+        ) {
+            (Some(raw), Some(field)) if !field.is_string() => {
+                *field = serde_json::Value::String(raw.clone())
+            }
+            _ => return Err(e),
+        }
+    }
 }
 
 impl Config {
+    fn parse(file: &str, contents: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let extension = std::path::Path::new(file)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        Ok(match extension.as_str() {
+            "toml" => toml::from_str(contents)?,
+            "yaml" | "yml" => serde_yaml::from_str(contents)?,
+            _ => serde_json::from_str(contents)?,
+        })
+    }
+
+    // values are parsed as JSON if possible, so numbers, booleans and lists can be
+    // given as well, and taken as strings otherwise; returns the raw values of the
+    // parsed ones by JSON pointer, to fall back to them where a string is expected.
+    // this runs before the logger is set up, so variables that cannot be applied
+    // are reported as errors instead of warnings nobody would see
+    fn apply_env_overrides(
+        value: &mut serde_json::Value,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<HashMap<String, String>, ConfigErrors> {
+        let mut vars: Vec<(String, Vec<String>, String)> = vars
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .map(|(name, raw)| {
+                let segments = name[ENV_PREFIX.len()..]
+                    .split('_')
+                    .filter(|segment| !segment.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                (name, segments, raw)
+            })
+            .collect();
+        // list indexes are compared as numbers, so SQC_SERVERS_10 comes after SQC_SERVERS_2
+        vars.sort_by_cached_key(|(_, segments, _)| {
+            segments
+                .iter()
+                .map(|segment| (segment.parse::<usize>().ok(), segment.clone()))
+                .collect::<Vec<_>>()
+        });
+
+        let mut parsed = HashMap::new();
+        let mut errors = Vec::new();
+        for (name, segments, raw) in vars {
+            if segments.is_empty() {
+                continue;
+            }
+
+            let new = serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw.clone()));
+            let is_string = new.is_string();
+            match set_path(value, &segments, new) {
+                Some(pointer) if !is_string => {
+                    parsed.insert(pointer, raw);
+                }
+                Some(_) => {}
+                None => errors.push(ConfigError {
+                    field: name,
+                    message: "List entries have to be added in order".to_string(),
+                }),
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(parsed),
+            false => Err(ConfigErrors(errors)),
+        }
+    }
+
     pub async fn load(file: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = tokio::fs::read_to_string(file).await?;
-        let config: Config = serde_json::from_str(&file)?;
+        let has_env_overrides = std::env::vars().any(|(name, _)| name.starts_with(ENV_PREFIX));
+        let mut value = match tokio::fs::read_to_string(&file).await {
+            Ok(contents) => Self::parse(&file, &contents)?,
+            // the whole config may be given in environment variables
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && has_env_overrides => {
+                serde_json::Value::Object(serde_json::Map::new())
+            }
+            Err(e) => return Err(format!("{}: {}", file, e).into()),
+        };
+        let env = Self::apply_env_overrides(&mut value, std::env::vars())?;
+
+        let mut config = Self::deserialize(value, &env)?;
+        config.validate().await?;
+
+        for server in config.servers.iter_mut() {
+            server.cache = server.cache.or(&config.cache);
+        }
+
         Ok(config)
     }
+
+    // reports the path of every field that failed to parse, e.g.
+    // servers[0] (name).cache.infoTtl, instead of stopping at the first one
+    fn deserialize(
+        mut value: serde_json::Value,
+        env: &HashMap<String, String>,
+    ) -> Result<Self, ConfigErrors> {
+        fn error(field: String, e: serde_path_to_error::Error<serde_json::Error>) -> ConfigError {
+            let path = e.path().to_string();
+            ConfigError {
+                field: match path.as_str() {
+                    "." => field,
+                    _ if field.is_empty() => path,
+                    _ => format!("{}.{}", field, path),
+                },
+                message: e.into_inner().to_string(),
+            }
+        }
+
+        let mut errors: Vec<ConfigError> = Vec::new();
+        let servers = value.as_object_mut().and_then(|map| map.remove("servers"));
+
+        let config = deserialize_coerced::<Config>(value, "", env)
+            .map_err(|e| errors.push(error(String::new(), e)))
+            .ok();
+
+        let servers = match servers {
+            Some(serde_json::Value::Array(servers)) => servers,
+            Some(other) => {
+                if let Err(e) = serde_path_to_error::deserialize::<_, Vec<ServerConfig>>(other) {
+                    errors.push(error("servers".to_string(), e));
+                }
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let mut parsed: Vec<ServerConfig> = Vec::with_capacity(servers.len());
+        for (i, server) in servers.into_iter().enumerate() {
+            let field = match server.get("name").and_then(|name| name.as_str()) {
+                Some(name) => format!("servers[{}] ({})", i, name),
+                None => format!("servers[{}]", i),
+            };
+            match deserialize_coerced::<ServerConfig>(server, &format!("/servers/{}", i), env) {
+                Ok(server) => parsed.push(server),
+                Err(e) => errors.push(error(field, e)),
+            }
+        }
+
+        match config {
+            Some(mut config) if errors.is_empty() => {
+                config.servers = parsed;
+                Ok(config)
+            }
+            _ => Err(ConfigErrors(errors)),
+        }
+    }
+
+    /// Checks the whole config and reports every problem at once, so they can
+    /// be fixed before any server is started.
+    pub async fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut validator = Validator::default();
+
+        if self.servers.is_empty() {
+            validator.error("servers", "No servers configured");
+        }
+
+        validator.cache("cache", &self.cache, &CacheConfig::default());
+        if let Some(log_level) = &self.log_level {
+            validator.log_level(log_level);
+        }
+
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        let mut binds: Vec<(SocketAddr, usize)> = Vec::new();
+        for (i, server) in self.servers.iter().enumerate() {
+            let field = format!("servers[{}] ({})", i, server.name);
+
+            if let Some(other) = names.insert(&server.name, i) {
+                validator.error(
+                    format!("{}.name", field),
+                    format!("Already used by servers[{}]", other),
+                );
+            }
+
+            if let Some(bind) = validator.server(&field, server, &self.cache).await {
+                // an unspecified address takes the port on every address
+                let conflict = binds.iter().find(|(other, _)| {
+                    other.port() == bind.port()
+                        && (other.ip() == bind.ip()
+                            || other.ip().is_unspecified()
+                            || bind.ip().is_unspecified())
+                });
+                match conflict {
+                    Some((other, j)) => validator.error(
+                        format!("{}.bind", field),
+                        format!("{} conflicts with {} of servers[{}]", bind, other, j),
+                    ),
+                    None => binds.push((bind, i)),
+                }
+            }
+        }
+
+        if let Some(http) = &self.http {
+            validator.resolve("http.bind".to_string(), &http.bind).await;
+        }
+
+        match validator.errors.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigErrors(validator.errors)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(result: Result<(), ConfigErrors>) -> Vec<String> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(ConfigErrors(errors)) => errors.into_iter().map(|error| error.field).collect(),
+        }
+    }
+
+    fn config(value: serde_json::Value) -> Config {
+        Config::deserialize(value, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn collects_parse_errors_of_every_server() {
+        let errors = Config::deserialize(
+            serde_json::json!({
+            "servers": [
+                {"name": "a", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27016", "workers": -1},
+                {"name": "b", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27017"},
+                {"name": "c", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27018", "cache": {"ttl": 1}},
+            ],
+        }),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+
+        let fields: Vec<&str> = errors.0.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            ["servers[0] (a).workers", "servers[2] (c).cache.ttl"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_zero_ttl_in_background_mode() {
+        let config = config(serde_json::json!({
+            "cache": {"mode": "background", "playerTtl": 0},
+            "servers": [
+                {"name": "a", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27016"},
+                {"name": "b", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27017", "cache": {"infoTtl": 0}},
+                {"name": "c", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27018", "cache": {"mode": "lazy", "infoTtl": 0}},
+            ],
+        }));
+
+        assert_eq!(
+            fields(config.validate().await),
+            ["cache.playerTtl", "servers[1] (b).cache.infoTtl"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_binds_overlapping_an_unspecified_address() {
+        let config = config(serde_json::json!({
+            "servers": [
+                {"name": "a", "host": "127.0.0.1:27015", "bind": "0.0.0.0:27016"},
+                {"name": "b", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27016"},
+                {"name": "c", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27017"},
+                {"name": "d", "host": "127.0.0.1:27015", "bind": "127.0.0.2:27017"},
+            ],
+        }));
+
+        assert_eq!(fields(config.validate().await), ["servers[1] (b).bind"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_low_ratios_and_invalid_ranges() {
+        let config = config(serde_json::json!({
+            "servers": [{
+                "name": "a",
+                "host": "127.0.0.1:27015",
+                "bind": "127.0.0.1:27016",
+                "amplificationRatio": 0.5,
+                "allowlist": ["10.0.0.0/8", "192.0.2.1"],
+                "denylist": ["192.0.2.1", "10.0.0.0/40"],
+            }],
+        }));
+
+        assert_eq!(
+            fields(config.validate().await),
+            ["servers[0] (a).denylist[1]", "servers[0] (a).amplificationRatio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_log_levels() {
+        let mut config = config(serde_json::json!({
+            "servers": [{"name": "a", "host": "127.0.0.1:27015", "bind": "127.0.0.1:27016"}],
+            "logLevel": "info,steam_query_cacher::server=debug",
+        }));
+        assert!(config.validate().await.is_ok());
+
+        config.log_level = Some("verbose".to_string());
+        assert_eq!(fields(config.validate().await), ["logLevel"]);
+    }
+
+    #[test]
+    fn parses_toml_and_yaml_by_extension() {
+        let json = Config::parse(
+            "config.json",
+            r#"{"logLevel": "info", "servers": [{"name": "a", "cache": {"ttl": 5}, "allowlist": ["10.0.0.0/8"]}]}"#,
+        )
+        .unwrap();
+
+        let toml = Config::parse(
+            "config.TOML",
+            r#"
+logLevel = "info"
+
+[[servers]]
+name = "a"
+allowlist = ["10.0.0.0/8"]
+
+[servers.cache]
+ttl = 5
+"#,
+        )
+        .unwrap();
+        assert_eq!(toml, json);
+
+        let yaml = r#"
+logLevel: info
+servers:
+  - name: a
+    cache:
+      ttl: 5
+    allowlist: ["10.0.0.0/8"]
+"#;
+        assert_eq!(Config::parse("config.yaml", yaml).unwrap(), json);
+        assert_eq!(Config::parse("config.yml", yaml).unwrap(), json);
+    }
+
+    fn segments(name: &str) -> Vec<String> {
+        name.split('_').map(str::to_string).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> (serde_json::Value, HashMap<String, String>) {
+        let mut value = serde_json::json!({});
+        let parsed = Config::apply_env_overrides(
+            &mut value,
+            vars.iter()
+                .map(|(name, raw)| (name.to_string(), raw.to_string())),
+        )
+        .unwrap();
+        (value, parsed)
+    }
+
+    #[test]
+    fn camel_cases_segments() {
+        assert_eq!(camel_case(&segments("INFO_TTL")), "infoTtl");
+        assert_eq!(camel_case(&segments("IPV4_PREFIX")), "ipv4Prefix");
+        assert_eq!(camel_case(&segments("RATE_LIMIT")), "rateLimit");
+    }
+
+    #[test]
+    fn sets_nested_paths() {
+        let mut value = serde_json::json!({});
+        let pointers = [
+            ("SERVERS_0_CACHE_INFO_TTL", serde_json::json!(5)),
+            ("SERVERS_0_RATE_LIMIT_IPV4_PREFIX", serde_json::json!(24)),
+            (
+                "SERVERS_0_RULES_FILTER_INJECT_SV_TAGS",
+                serde_json::json!("cached"),
+            ),
+        ]
+        .map(|(name, new)| set_path(&mut value, &segments(name), new));
+
+        assert_eq!(
+            value,
+            serde_json::json!({"servers": [{
+                "cache": {"infoTtl": 5},
+                "rateLimit": {"ipv4Prefix": 24},
+                "rulesFilter": {"inject": {"sv_tags": "cached"}},
+            }]})
+        );
+        assert_eq!(
+            pointers,
+            [
+                Some("/servers/0/cache/infoTtl".to_string()),
+                Some("/servers/0/rateLimit/ipv4Prefix".to_string()),
+                Some("/servers/0/rulesFilter/inject/sv_tags".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_appends_list_entries() {
+        let mut value = serde_json::json!({});
+        let vars = [
+            ("SQC_SERVERS_0_NAME", "a"),
+            ("SQC_SERVERS_2_NAME", "c"),
+            ("SQC_SERVERS_99999999999_NAME", "d"),
+        ]
+        .map(|(name, raw)| (name.to_string(), raw.to_string()));
+        let errors = match Config::apply_env_overrides(&mut value, vars.into_iter()) {
+            Ok(_) => panic!("out of order list entries were applied"),
+            Err(ConfigErrors(errors)) => errors,
+        };
+        assert_eq!(value, serde_json::json!({"servers": [{"name": "a"}]}));
+        assert_eq!(
+            errors.into_iter().map(|e| e.field).collect::<Vec<_>>(),
+            ["SQC_SERVERS_2_NAME", "SQC_SERVERS_99999999999_NAME"]
+        );
+
+        let vars: Vec<(String, String)> = (0..12)
+            .map(|i| (format!("SQC_SERVERS_{}_NAME", i), format!("s{}", i)))
+            .collect();
+        let mut value = serde_json::json!({});
+        Config::apply_env_overrides(&mut value, vars.into_iter()).unwrap();
+        assert_eq!(value["servers"].as_array().unwrap().len(), 12);
+        assert_eq!(value["servers"][10]["name"], "s10");
+    }
+
+    #[test]
+    fn coerces_parsed_values_to_strings_where_expected() {
+        let (value, parsed) = env(&[
+            ("SQC_SERVERS_0_NAME", "1.0"),
+            ("SQC_SERVERS_0_HOST", "127.0.0.1:27015"),
+            ("SQC_SERVERS_0_BIND", "127.0.0.1:27016"),
+            ("SQC_SERVERS_0_CACHE_INFO_TTL", "5"),
+            ("SQC_SERVERS_0_RULES_FILTER_INJECT_SV_TAGS", "true"),
+            ("SQC_SERVERS_0_RULES_FILTER_INJECT_VERSION", "0.10"),
+        ]);
+        let config = Config::deserialize(value, &parsed).unwrap();
+
+        let server = &config.servers[0];
+        assert_eq!(server.name, "1.0");
+        assert_eq!(server.cache.info_ttl, Some(5));
+        let inject = &server.rules_filter.inject;
+        assert_eq!(inject["sv_tags"], "true");
+        assert_eq!(inject["version"], "0.10");
+
+        let (value, parsed) = env(&[
+            ("SQC_SERVERS_0_NAME", "a"),
+            ("SQC_SERVERS_0_HOST", "127.0.0.1:27015"),
+            ("SQC_SERVERS_0_BIND", "127.0.0.1:27016"),
+            ("SQC_SERVERS_0_CACHE_INFO_TTL", "\"5\""),
+        ]);
+        assert!(Config::deserialize(value, &parsed).is_err());
+    }
 }
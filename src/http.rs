@@ -0,0 +1,199 @@
+use std::{sync::Arc, time};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{metrics, server::SteamQueryCacheServer, supervisor::ServerList};
+
+pub const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+pub const MAX_REQUEST_SIZE: usize = 8192;
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(value: &impl serde::Serialize) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status: "200 OK",
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::text("500 Internal Server Error", &format!("{}\n", e)),
+        }
+    }
+
+    fn text(status: &'static str, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.to_string(),
+        }
+    }
+}
+
+// a minimal HTTP/1.1 listener for metrics and the status of the cached servers,
+// every connection serves a single request
+pub struct HttpServer {
+    listener: TcpListener,
+    servers: ServerList,
+}
+
+impl HttpServer {
+    pub async fn bind(addr: &str, servers: ServerList) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            servers,
+        })
+    }
+
+    pub async fn serve(self) {
+        match self.listener.local_addr() {
+            Ok(addr) => log::info!("HTTP listening on {}", addr),
+            Err(e) => log::error!("Failed to get HTTP listener address: {}", e),
+        }
+
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::error!("Failed to accept HTTP connection: {}", e);
+                    continue;
+                }
+            };
+
+            // servers may be added or removed by a reload while the request runs
+            let servers: Vec<Arc<SteamQueryCacheServer>> = self.servers.read().unwrap().clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle(stream, &servers).await {
+                    log::debug!("Failed to handle HTTP request from {}: {}", addr, e);
+                }
+            });
+        }
+    }
+}
+
+async fn read_request_line(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut buf: Vec<u8> = Vec::with_capacity(1024);
+
+    // the body of a GET request is ignored, so reading the head is enough
+    while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Request too large",
+            ));
+        }
+
+        let mut chunk = [0u8; 1024];
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed before the request was complete",
+            ));
+        }
+        buf.extend(&chunk[..len]);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    Ok(head.lines().next().unwrap_or_default().to_string())
+}
+
+// server names may contain spaces and other characters that are escaped in paths
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+async fn route(request_line: &str, servers: &[Arc<SteamQueryCacheServer>]) -> Response {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Response::text("400 Bad Request", "Bad request\n"),
+    };
+    let path = target.split('?').next().unwrap_or_default();
+
+    if method != "GET" {
+        return Response::text("405 Method Not Allowed", "Method not allowed\n");
+    }
+
+    match path {
+        "/metrics" => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: metrics::render(servers),
+        },
+        "/status" => {
+            let mut statuses = Vec::with_capacity(servers.len());
+            for server in servers {
+                statuses.push(server.status().await);
+            }
+
+            Response::json(&statuses)
+        }
+        _ => match path
+            .strip_prefix("/status/")
+            .map(percent_decode)
+            .and_then(|name| servers.iter().find(|server| server.name() == name))
+        {
+            Some(server) => Response::json(&server.status().await),
+            None => Response::text("404 Not Found", "Not found\n"),
+        },
+    }
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    servers: &[Arc<SteamQueryCacheServer>],
+) -> std::io::Result<()> {
+    let request_line =
+        match tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream)).await {
+            Ok(request_line) => request_line?,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out reading request",
+                ))
+            }
+        };
+
+    let response = route(&request_line, servers).await;
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
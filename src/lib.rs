@@ -1,7 +1,10 @@
 pub mod config;
+pub mod http;
 pub mod server;
+pub mod supervisor;
 
 mod client;
+mod metrics;
 mod timed_hashmap;
 
 pub use config::Config;
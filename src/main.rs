@@ -1,25 +1,32 @@
 use clap::Parser;
 
-use steam_query_cacher::{Config, SteamQueryCacheServer};
-use tokio::task::JoinSet;
+use steam_query_cacher::{http::HttpServer, supervisor::Supervisor, Config};
 
 #[derive(Debug, Parser)]
 struct Args {
     #[arg(short, long, default_value = "config.json")]
     config: String,
+    /// Validate the config and exit
+    #[arg(long)]
+    check: bool,
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
+    dotenv::dotenv().ok();
 
-    let config: Config = match Config::load(args.config).await {
+    let config: Config = match Config::load(args.config.clone()).await {
         Ok(config) => config,
         Err(e) => {
             eprintln!("Failed to load config: {}", e);
-            return Ok(());
+            std::process::exit(1);
         }
     };
+    if args.check {
+        println!("Config {} is valid", args.config);
+        return Ok(());
+    }
     let default_log_level: String = match config.log_level.clone() {
         Some(log_level) => log_level,
         None => "info".to_string(),
@@ -29,30 +36,19 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("Loaded config: {:?}", config);
 
-    // create a tokio join set
-    let mut set = JoinSet::new();
-
-    for server in config.servers {
-        let server = SteamQueryCacheServer::new(server).await?;
+    let http = config.http.clone();
+    let supervisor = Supervisor::start(args.config, config).await?;
 
-        set.spawn(async move {
-            server.listen().await;
-        });
+    if let Some(http) = http {
+        let http = HttpServer::bind(&http.bind, supervisor.servers()).await?;
+        tokio::spawn(http.serve());
     }
 
-    let join_all = async {
-        while let Some(result) = set.join_next().await {
-            result?;
-        }
-        Ok::<(), tokio::task::JoinError>(())
-    };
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             log::info!("Ctrl-C received, shutting down");
         }
-        _ = join_all => {
-            log::info!("All servers shut down");
-        }
+        _ = supervisor.run() => {}
     };
 
     Ok(())
@@ -0,0 +1,261 @@
+use std::{
+    fmt::{Display, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time,
+};
+
+use crate::server::SteamQueryCacheServer;
+
+// seconds
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: buckets.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: time::Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = self.buckets.iter().position(|le| seconds <= *le) {
+            self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// writes metrics in the Prometheus text exposition format
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!("{}=\"{}\"", key, value)
+            })
+            .collect();
+        let _ = writeln!(self.out, "{}{{{}}} {}", name, labels.join(","), value);
+    }
+
+    fn histogram(&mut self, name: &str, server: &str, histogram: &Histogram) {
+        let mut cumulative = 0;
+        for (le, count) in histogram.buckets.iter().zip(&histogram.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            self.sample(
+                &format!("{}_bucket", name),
+                &[("server", server), ("le", &le.to_string())],
+                cumulative,
+            );
+        }
+
+        let count = histogram.count.load(Ordering::Relaxed);
+        self.sample(
+            &format!("{}_bucket", name),
+            &[("server", server), ("le", "+Inf")],
+            count,
+        );
+        self.sample(
+            &format!("{}_sum", name),
+            &[("server", server)],
+            histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        );
+        self.sample(&format!("{}_count", name), &[("server", server)], count);
+    }
+}
+
+pub fn render(servers: &[Arc<SteamQueryCacheServer>]) -> String {
+    let mut writer = MetricsWriter { out: String::new() };
+
+    writer.family(
+        "sqc_packets_received_total",
+        "counter",
+        "Packets received by query header",
+    );
+    for server in servers {
+        for (header, count) in server.packets_received() {
+            writer.sample(
+                "sqc_packets_received_total",
+                &[("server", server.name()), ("header", &header)],
+                count,
+            );
+        }
+    }
+
+    writer.family(
+        "sqc_packets_dropped_total",
+        "counter",
+        "Packets dropped before they were handled",
+    );
+    for server in servers {
+        for (reason, count) in [
+            ("denylist", server.denied_packets()),
+            ("ban", server.banned_packets()),
+            ("rate_limit", server.rate_limited_packets()),
+            ("overload", server.overloaded_packets()),
+        ] {
+            writer.sample(
+                "sqc_packets_dropped_total",
+                &[("server", server.name()), ("reason", reason)],
+                count,
+            );
+        }
+    }
+
+    writer.family(
+        "sqc_cache_requests_total",
+        "counter",
+        "Cache lookups by query and result",
+    );
+    for server in servers {
+        for (query, stats) in server.cache_stats() {
+            for (result, count) in [
+                ("hit", &stats.hits),
+                ("stale", &stats.stale_hits),
+                ("offline", &stats.offline_hits),
+                ("miss", &stats.misses),
+            ] {
+                writer.sample(
+                    "sqc_cache_requests_total",
+                    &[
+                        ("server", server.name()),
+                        ("query", query),
+                        ("result", result),
+                    ],
+                    count.load(Ordering::Relaxed),
+                );
+            }
+        }
+    }
+
+    writer.family(
+        "sqc_cache_refreshes_total",
+        "counter",
+        "Upstream refreshes by query and result",
+    );
+    for server in servers {
+        for (query, stats) in server.cache_stats() {
+            for (result, count) in [
+                ("success", &stats.refreshes),
+                ("failure", &stats.refresh_failures),
+            ] {
+                writer.sample(
+                    "sqc_cache_refreshes_total",
+                    &[
+                        ("server", server.name()),
+                        ("query", query),
+                        ("result", result),
+                    ],
+                    count.load(Ordering::Relaxed),
+                );
+            }
+        }
+    }
+
+    writer.family(
+        "sqc_upstream_latency_seconds",
+        "histogram",
+        "Latency of successful upstream queries",
+    );
+    for server in servers {
+        writer.histogram(
+            "sqc_upstream_latency_seconds",
+            server.name(),
+            &server.upstream_latency(),
+        );
+    }
+
+    writer.family(
+        "sqc_upstream_errors_total",
+        "counter",
+        "Failed upstream queries by kind",
+    );
+    for server in servers {
+        for (kind, count) in [
+            ("timeout", server.upstream_timeouts()),
+            ("other", server.upstream_errors()),
+        ] {
+            writer.sample(
+                "sqc_upstream_errors_total",
+                &[("server", server.name()), ("kind", kind)],
+                count,
+            );
+        }
+    }
+
+    writer.family(
+        "sqc_challenges_total",
+        "counter",
+        "Challenges issued to clients and rejected from them",
+    );
+    for server in servers {
+        for (result, count) in [
+            ("issued", server.challenges_issued()),
+            ("rejected", server.challenges_rejected()),
+        ] {
+            writer.sample(
+                "sqc_challenges_total",
+                &[("server", server.name()), ("result", result)],
+                count,
+            );
+        }
+    }
+
+    writer.family(
+        "sqc_connection_pool_clients",
+        "gauge",
+        "Clients tracked in the connection pool",
+    );
+    for server in servers {
+        writer.sample(
+            "sqc_connection_pool_clients",
+            &[("server", server.name())],
+            server.tracked_clients(),
+        );
+    }
+
+    writer.family(
+        "sqc_connection_pool_evictions_total",
+        "counter",
+        "Clients evicted from the full connection pool",
+    );
+    for server in servers {
+        writer.sample(
+            "sqc_connection_pool_evictions_total",
+            &[("server", server.name())],
+            server.evicted_clients(),
+        );
+    }
+
+    writer.out
+}
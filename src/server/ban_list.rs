@@ -0,0 +1,155 @@
+use std::{net::IpAddr, time};
+
+use crate::{config::BanConfig, timed_hashmap::TimedHashMap};
+
+pub const DEFAULT_STRIKES: u32 = 5;
+pub const DEFAULT_STRIKE_WINDOW: u64 = 60;
+pub const DEFAULT_BAN_DURATION: u64 = 60;
+pub const DEFAULT_MAX_BAN_DURATION: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strike {
+    InvalidHeader,
+    MalformedPacket,
+    WrongChallenge,
+    RateLimited,
+}
+
+#[derive(Debug)]
+pub struct BanList {
+    strikes: TimedHashMap<IpAddr, u32>,
+    // number of previous bans, used to escalate the ban duration
+    offenses: TimedHashMap<IpAddr, u32>,
+    bans: TimedHashMap<IpAddr, ()>,
+    max_strikes: u32,
+    strike_window: time::Duration,
+    ban_duration: time::Duration,
+    max_ban_duration: time::Duration,
+}
+
+impl BanList {
+    pub async fn new(config: &BanConfig) -> Self {
+        Self {
+            strikes: TimedHashMap::new(false, None).await,
+            offenses: TimedHashMap::new(false, None).await,
+            bans: TimedHashMap::new(false, None).await,
+            max_strikes: config.strikes.unwrap_or(DEFAULT_STRIKES).max(1),
+            strike_window: time::Duration::from_secs(
+                config.strike_window.unwrap_or(DEFAULT_STRIKE_WINDOW),
+            ),
+            ban_duration: time::Duration::from_secs(
+                config.duration.unwrap_or(DEFAULT_BAN_DURATION),
+            ),
+            max_ban_duration: time::Duration::from_secs(
+                config.max_duration.unwrap_or(DEFAULT_MAX_BAN_DURATION),
+            ),
+        }
+    }
+
+    pub async fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans.get(&ip.to_canonical()).await.is_some()
+    }
+
+    // every further ban doubles the duration, up to the configured maximum
+    fn duration(&self, offenses: u32) -> time::Duration {
+        self.ban_duration
+            .saturating_mul(2u32.saturating_pow(offenses))
+            .min(self.max_ban_duration)
+    }
+
+    pub async fn strike(&self, ip: IpAddr, strike: Strike) {
+        // dual-stack sockets report IPv4 clients as mapped IPv6 addresses
+        let ip = ip.to_canonical();
+        let strikes = self.strikes.get(&ip).await.unwrap_or_default() + 1;
+        log::debug!(
+            "Strike {}/{} for {}: {:?}",
+            strikes,
+            self.max_strikes,
+            ip,
+            strike
+        );
+
+        if strikes < self.max_strikes {
+            self.strikes.insert(ip, strikes, self.strike_window).await;
+            return;
+        }
+        self.strikes.remove(&ip).await;
+
+        let offenses = self.offenses.get(&ip).await.unwrap_or_default();
+        let duration = self.duration(offenses);
+
+        log::warn!(
+            "Banning {} for {}s after {} strikes (last: {:?})",
+            ip,
+            duration.as_secs(),
+            strikes,
+            strike
+        );
+
+        self.bans.insert(ip, (), duration).await;
+        // offenses are forgotten once an address behaved for as long as the longest ban
+        self.offenses
+            .insert(ip, offenses + 1, duration + self.max_ban_duration)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ban_list(strikes: u32) -> BanList {
+        BanList::new(&BanConfig {
+            strikes: Some(strikes),
+            strike_window: None,
+            duration: Some(10),
+            max_duration: Some(60),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn bans_after_enough_strikes() {
+        let ban_list = ban_list(3).await;
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        ban_list.strike(ip, Strike::InvalidHeader).await;
+        ban_list.strike(ip, Strike::WrongChallenge).await;
+        assert!(!ban_list.is_banned(&ip).await);
+
+        ban_list.strike(ip, Strike::MalformedPacket).await;
+        assert!(ban_list.is_banned(&ip).await);
+        assert!(!ban_list.is_banned(&"192.0.2.2".parse().unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn treats_mapped_addresses_as_ipv4() {
+        let ban_list = ban_list(2).await;
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        ban_list.strike(ip, Strike::RateLimited).await;
+        ban_list
+            .strike("::ffff:192.0.2.1".parse().unwrap(), Strike::RateLimited)
+            .await;
+
+        assert!(ban_list.is_banned(&ip).await);
+        assert!(
+            ban_list
+                .is_banned(&"::ffff:192.0.2.1".parse().unwrap())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn escalates_ban_duration() {
+        let ban_list = ban_list(1).await;
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let durations: Vec<u64> = (0..5).map(|n| ban_list.duration(n).as_secs()).collect();
+        assert_eq!(durations, [10, 20, 40, 60, 60]);
+
+        ban_list.strike(ip, Strike::InvalidHeader).await;
+        ban_list.strike(ip, Strike::InvalidHeader).await;
+        assert_eq!(ban_list.offenses.get(&ip).await, Some(2));
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::client::packets::SourceChallenge;
+
+pub const CHALLENGE_WINDOW: time::Duration = time::Duration::from_secs(30);
+pub const REPORT_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+// challenges are derived from the client address and the current time window,
+// so validating them needs no per-client state
+#[derive(Debug)]
+pub struct ChallengeGenerator {
+    key: [u8; 32],
+    started: time::Instant,
+    issued: Arc<AtomicU64>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl ChallengeGenerator {
+    pub fn new() -> Self {
+        let instance = Self {
+            key: rand::random(),
+            started: time::Instant::now(),
+            issued: Arc::new(AtomicU64::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        };
+
+        instance.start_report_task();
+
+        instance
+    }
+
+    pub fn issued(&self) -> u64 {
+        self.issued.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn start_report_task(&self) {
+        let rejected = Arc::downgrade(&self.rejected);
+
+        tokio::spawn(async move {
+            let mut reported: u64 = 0;
+
+            loop {
+                tokio::time::sleep(REPORT_INTERVAL).await;
+
+                let total = match rejected.upgrade() {
+                    Some(rejected) => rejected.load(Ordering::Relaxed),
+                    None => break,
+                };
+
+                if total > reported {
+                    log::warn!(
+                        "Rejected {} wrong challenges in the last {}s ({} total)",
+                        total - reported,
+                        REPORT_INTERVAL.as_secs(),
+                        total
+                    );
+                    reported = total;
+                }
+            }
+        });
+    }
+
+    fn window(&self) -> u64 {
+        self.started.elapsed().as_secs() / CHALLENGE_WINDOW.as_secs()
+    }
+
+    fn challenge_for(&self, addr: &SocketAddr, window: u64) -> SourceChallenge {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length is valid");
+        match addr.ip().to_canonical() {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        mac.update(&addr.port().to_le_bytes());
+        mac.update(&window.to_le_bytes());
+
+        let hash = mac.finalize().into_bytes();
+        let challenge = SourceChallenge::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]);
+
+        // -1 is what clients send when they do not have a challenge yet
+        if challenge == -1 {
+            0
+        } else {
+            challenge
+        }
+    }
+
+    pub fn get_challenge(&self, addr: &SocketAddr) -> SourceChallenge {
+        self.issued.fetch_add(1, Ordering::Relaxed);
+        self.challenge_for(addr, self.window())
+    }
+
+    /// Accepts challenges of the current and the previous time window.
+    pub fn validate(&self, addr: &SocketAddr, challenge: SourceChallenge) -> bool {
+        let window = self.window();
+
+        let valid = challenge == self.challenge_for(addr, window)
+            || (window > 0 && challenge == self.challenge_for(addr, window - 1));
+        if !valid {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+
+        valid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pretends the generator has been running for `windows` challenge windows
+    fn generator(windows: u32) -> ChallengeGenerator {
+        ChallengeGenerator {
+            key: rand::random(),
+            started: time::Instant::now() - CHALLENGE_WINDOW * windows - CHALLENGE_WINDOW / 2,
+            issued: Arc::new(AtomicU64::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    #[test]
+    fn accepts_issued_challenge() {
+        let challenges = generator(0);
+        let addr: SocketAddr = "192.0.2.1:27005".parse().unwrap();
+
+        let challenge = challenges.get_challenge(&addr);
+        assert!(challenges.validate(&addr, challenge));
+        assert!(challenges.validate(&"[::ffff:192.0.2.1]:27005".parse().unwrap(), challenge));
+        assert_eq!((challenges.issued(), challenges.rejected()), (1, 0));
+    }
+
+    #[test]
+    fn accepts_current_and_previous_window_only() {
+        let challenges = generator(2);
+        let addr: SocketAddr = "192.0.2.1:27005".parse().unwrap();
+
+        assert!(challenges.validate(&addr, challenges.challenge_for(&addr, 2)));
+        assert!(challenges.validate(&addr, challenges.challenge_for(&addr, 1)));
+        assert!(!challenges.validate(&addr, challenges.challenge_for(&addr, 0)));
+        assert!(!challenges.validate(&addr, challenges.challenge_for(&addr, 3)));
+        assert_eq!(challenges.rejected(), 2);
+    }
+
+    #[test]
+    fn rejects_challenge_of_another_address() {
+        let challenges = generator(0);
+        let addr: SocketAddr = "192.0.2.1:27005".parse().unwrap();
+        let challenge = challenges.get_challenge(&addr);
+
+        assert!(!challenges.validate(&"192.0.2.2:27005".parse().unwrap(), challenge));
+        assert!(!challenges.validate(&"192.0.2.1:27006".parse().unwrap(), challenge));
+        assert!(!challenges.validate(&addr, -1));
+        assert_eq!(challenges.rejected(), 3);
+    }
+}
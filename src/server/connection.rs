@@ -1,261 +1,348 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
-use once_cell::sync::Lazy;
-use tokio::{
-    net::UdpSocket,
-    sync::{mpsc, RwLock},
-};
+use tokio::net::UdpSocket;
 
-use crate::client::{
-    packets::{
+use crate::{
+    client::packets::{
         a2s_info::A2SInfo, a2s_player::A2SPlayer, a2s_rules::A2SRules, s2c_challenge::S2CChallenge,
         QueryHeader, SourceChallenge, SOURCE_PACKET_HEADER,
     },
-    SteamQueryClient,
+    config::ChallengePolicy,
+};
+
+use super::{
+    ban_list::Strike,
+    encoded_response::{with_header, EncodedResponse},
+    query_cache::CachedQuery,
+    socket::{self, DEFAULT_BATCH_SIZE},
+    state::ServerState,
 };
 
-use super::{challenge_cache::ChallengeCache, query_cache::QueryCacheManager};
+pub const DEFAULT_LOAD_THRESHOLD: u64 = 100;
+
+// what a response has to be fetched from the upstream server for
+#[derive(Debug)]
+pub enum Upstream {
+    Cached(CachedQuery),
+    Proxy(Vec<u8>),
+}
+
+#[derive(Debug)]
+enum Handled {
+    Response(EncodedResponse),
+    Upstream(Upstream),
+}
+
+/// A packet that cannot be answered before the upstream server replied.
+#[derive(Debug)]
+pub struct Deferred {
+    connection: Connection,
+    request_len: usize,
+    upstream: Upstream,
+}
+
+impl Deferred {
+    pub async fn complete(self) {
+        let Self {
+            mut connection,
+            request_len,
+            upstream,
+        } = self;
+
+        let result = match upstream {
+            Upstream::Cached(query) => connection.state.query_cache.query(query).await,
+            Upstream::Proxy(buf) => connection
+                .state
+                .client
+                .proxy_request(buf)
+                .await
+                .and_then(|response| EncodedResponse::new(response, connection.state.protocol)),
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                log::error!("Failed to handle packet from {}: {}", connection.addr, e);
+                return;
+            }
+        };
 
-pub static CONNECTION_POOL: Lazy<RwLock<HashMap<SocketAddr, Arc<mpsc::Sender<Vec<u8>>>>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
+        if let Err(e) = connection.respond(request_len, response).await {
+            log::error!("Failed to respond to {}: {}", connection.addr, e);
+        }
+    }
+}
 
+// a client address and what is known about it while one of its packets is handled
 #[derive(Debug)]
 pub struct Connection {
+    state: Arc<ServerState>,
+    // replies are sent from the socket the request arrived on
     socket: Arc<UdpSocket>,
     addr: SocketAddr,
-    client: Arc<SteamQueryClient>,
-    pub tx: Arc<mpsc::Sender<Vec<u8>>>,
-    rx: mpsc::Receiver<Vec<u8>>,
-    challenge_cache: Arc<ChallengeCache>,
-    query_cache: Arc<QueryCacheManager>,
+    allowlisted: bool,
+    // whether the client presented a valid challenge, proving it owns its address
+    challenged: bool,
+    // whether the query type does not require a challenge right now, so its
+    // responses are not limited by the amplification ratio either
+    exempt: bool,
 }
 
 impl Connection {
-    pub async fn new(
+    pub fn new(
+        state: Arc<ServerState>,
         socket: Arc<UdpSocket>,
-        client: Arc<SteamQueryClient>,
         addr: SocketAddr,
-        challenge_cache: Arc<ChallengeCache>,
-        query_cache: Arc<QueryCacheManager>,
+        allowlisted: bool,
     ) -> Self {
-        let (tx, rx) = tokio::sync::mpsc::channel(1_000);
-        let tx: Arc<mpsc::Sender<Vec<u8>>> = Arc::new(tx);
+        let challenged = state.connections.is_verified(&addr);
 
-        let instance: Self = Self {
+        Self {
+            state,
             socket,
-            client,
             addr,
-            rx,
-            tx,
-            challenge_cache,
-            query_cache,
-        };
-
-        instance
+            allowlisted,
+            challenged,
+            exempt: false,
+        }
     }
 
-    async fn read(&mut self) -> Result<Vec<u8>, std::io::Error> {
-        tokio::select! {
-            _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("Timed out")))
+    async fn send(&self, response: &EncodedResponse) -> Result<(), std::io::Error> {
+        match response.datagrams() {
+            [datagram] => {
+                self.socket.send_to(datagram, self.addr).await?;
             }
-            res = self.rx.recv() => {
-                match res {
-                    Some(data) => {
-                        log::trace!("Received {} bytes from {}", data.len(), self.addr);
-                        return Ok(data)
-                    }
-                    None => {
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to receive from channel")))
+            datagrams => {
+                log::trace!(
+                    "Sending {} bytes to {} as {} split packets",
+                    response.len(),
+                    self.addr,
+                    datagrams.len()
+                );
+                // a batch size of 1 disables batched sends as well
+                if self.state.config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE) > 1 {
+                    socket::send_all(&self.socket, datagrams, self.addr).await?;
+                } else {
+                    for datagram in datagrams {
+                        self.socket.send_to(datagram, self.addr).await?;
                     }
                 }
             }
-        };
-    }
+        }
+        log::trace!("Sent {} bytes to {}", response.len(), self.addr);
 
-    async fn send(&mut self, buf: Vec<u8>) -> Result<(), std::io::Error> {
-        self.socket.send_to(&buf, self.addr).await?;
-        log::trace!("Sent {} bytes to {}", buf.len(), self.addr);
         Ok(())
     }
 
-    async fn handle_connection(mut self) -> Result<(), std::io::Error> {
-        log::info!("Handling connection from {}", self.addr);
+    // only clients that answered a challenge can be struck, anyone could send
+    // junk with a spoofed source address to get another address banned
+    async fn strike(&self, strike: Strike) {
+        if self.allowlisted || !self.challenged {
+            return;
+        }
 
-        loop {
-            let buf = self.read().await?;
+        if let Some(ban_list) = &self.state.ban_list {
+            ban_list.strike(self.addr.ip(), strike).await;
+        }
+    }
 
-            let header = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-            if header != SOURCE_PACKET_HEADER {
-                log::warn!(
-                    "Received packet with invalid packet header from {}",
-                    self.addr
-                );
-                return Ok(());
+    async fn check_challenge(
+        &mut self,
+        policy: ChallengePolicy,
+        challenge: Option<SourceChallenge>,
+    ) -> bool {
+        let required = match policy {
+            ChallengePolicy::Always => true,
+            ChallengePolicy::Never => {
+                self.exempt = true;
+                false
             }
+            ChallengePolicy::UnderLoad => {
+                let threshold = self
+                    .state
+                    .challenge_policy
+                    .load_threshold
+                    .unwrap_or(DEFAULT_LOAD_THRESHOLD);
+                // below the threshold it is served like `never`, the ratio would
+                // still answer most queries with a challenge otherwise
+                let required = self.state.load.rate() > threshold;
+                self.exempt = !required;
+                required
+            }
+        };
 
-            let header: QueryHeader = match QueryHeader::try_from(buf[4]) {
-                Ok(header) => header,
-                Err(e) => {
-                    // TODO: blacklist ip
-                    log::warn!("Received invalid packet from {}: {}", self.addr, e);
-                    return Ok(());
-                }
-            };
-
-            if header == QueryHeader::A2SInfo {
-                let packet: A2SInfo = match A2SInfo::try_from(&buf.as_slice()[4..]) {
-                    Ok(packet) => packet,
-                    Err(e) => {
-                        log::warn!(
-                            "Received packet with invalid query header from {}: {}",
-                            self.addr,
-                            e
-                        );
-                        return Ok(());
-                    }
-                };
-
-                let challenge: SourceChallenge =
-                    self.challenge_cache.get_challenge(&self.addr).await;
-
-                if match packet.challenge {
-                    Some(challenge) => challenge != challenge,
-                    None => true,
-                } {
-                    log::trace!("Sending challenge to {}", self.addr);
-                    let s2c_challenge = S2CChallenge::new(challenge);
-                    let mut bytes: Vec<u8> = s2c_challenge.into();
-                    i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                        .iter()
-                        .for_each(|b| bytes.insert(0, *b));
-                    self.send(bytes).await?;
-                    continue;
-                }
+        if let Some(challenge) = challenge.filter(|challenge| *challenge != -1) {
+            if self.state.challenges.validate(&self.addr, challenge) {
+                self.state.connections.verify(self.addr);
+                self.challenged = true;
+                return true;
+            }
 
-                let a2s_info = self.query_cache.a2s_info().await?;
-                let mut bytes: Vec<u8> = a2s_info.into();
-                i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                    .iter()
-                    .for_each(|b| bytes.insert(0, *b));
+            if required {
+                log::debug!("Rejected wrong challenge from {}", self.addr);
+                self.strike(Strike::WrongChallenge).await;
+            }
+        }
 
-                log::trace!(
-                    "Sending {} bytes to {}: {:?}",
-                    bytes.len(),
-                    self.addr,
-                    bytes
-                );
+        !required
+    }
 
-                self.send(bytes).await?;
-            } else if header == QueryHeader::A2SPlayer {
-                let packet: A2SPlayer = match A2SPlayer::try_from(&buf.as_slice()[4..]) {
-                    Ok(packet) => packet,
-                    Err(e) => {
-                        log::warn!("Received invalid packet from {}: {}", self.addr, e);
-                        return Ok(());
-                    }
-                };
-
-                let challenge: SourceChallenge =
-                    self.challenge_cache.get_challenge(&self.addr).await;
-
-                if match packet.challenge {
-                    Some(challenge) => challenge != challenge,
-                    None => true,
-                } {
-                    log::trace!("Sending challenge to {}", self.addr);
-                    let s2c_challenge = S2CChallenge::new(challenge);
-                    let mut bytes: Vec<u8> = s2c_challenge.into();
-                    i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                        .iter()
-                        .for_each(|b| bytes.insert(0, *b));
-                    self.send(bytes).await?;
-                    continue;
-                }
+    fn challenge_response(&self) -> EncodedResponse {
+        log::trace!("Sending challenge to {}", self.addr);
+        let s2c_challenge = S2CChallenge::new(self.state.challenges.get_challenge(&self.addr));
 
-                let a2s_player = self.query_cache.a2s_player().await?;
-                let mut bytes: Vec<u8> = a2s_player.into();
-                i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                    .iter()
-                    .for_each(|b| bytes.insert(0, *b));
+        EncodedResponse::single(with_header(s2c_challenge.into()))
+    }
 
-                log::trace!(
-                    "Sending {} bytes to {}: {:?}",
-                    bytes.len(),
-                    self.addr,
-                    bytes
+    // responses to addresses that never proved they own them with a challenge may
+    // not be larger than the request times the amplification ratio, so they cannot
+    // be used for reflection attacks
+    async fn respond(
+        &mut self,
+        request_len: usize,
+        response: EncodedResponse,
+    ) -> std::io::Result<()> {
+        let max_len = (request_len as f64 * self.state.amplification_ratio) as usize;
+        if !self.challenged && !self.exempt && response.len() > max_len {
+            let challenge = self.challenge_response();
+            if challenge.len() > max_len {
+                log::debug!(
+                    "Dropping {} byte response to unchallenged {}, even a challenge exceeds the amplification ratio",
+                    response.len(),
+                    self.addr
                 );
+                return Ok(());
+            }
 
-                self.send(bytes).await?;
-            } else if header == QueryHeader::A2SRules {
-                let packet: A2SRules = match A2SRules::try_from(&buf.as_slice()[4..]) {
-                    Ok(packet) => packet,
-                    Err(e) => {
-                        log::warn!("Received invalid packet from {}: {}", self.addr, e);
-                        return Ok(());
-                    }
-                };
-
-                let challenge: SourceChallenge =
-                    self.challenge_cache.get_challenge(&self.addr).await;
-
-                if match packet.challenge {
-                    Some(challenge) => challenge != challenge,
-                    None => true,
-                } {
-                    log::trace!("Sending challenge to {}", self.addr);
-                    let s2c_challenge = S2CChallenge::new(challenge);
-                    let mut bytes: Vec<u8> = s2c_challenge.into();
-                    i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                        .iter()
-                        .for_each(|b| bytes.insert(0, *b));
-                    self.send(bytes).await?;
-                    continue;
-                }
-
-                let a2s_rules = self.query_cache.a2s_rules().await?;
-                let mut bytes: Vec<u8> = a2s_rules.into();
-                i32::to_le_bytes(SOURCE_PACKET_HEADER)
-                    .iter()
-                    .for_each(|b| bytes.insert(0, *b));
+            log::debug!(
+                "Replacing {} byte response to unchallenged {} with a challenge",
+                response.len(),
+                self.addr
+            );
+            return self.send(&challenge).await;
+        }
 
-                log::trace!(
-                    "Sending {} bytes to {}: {:?}",
-                    bytes.len(),
-                    self.addr,
-                    bytes
-                );
+        self.send(&response).await
+    }
 
-                self.send(bytes).await?;
-            } else {
-                let resp = self.client.proxy_request(buf).await?;
-                self.send(resp).await?;
-            }
+    async fn cached(&self, query: CachedQuery) -> Handled {
+        match self.state.query_cache.lookup(query).await {
+            Some(response) => Handled::Response(response),
+            None => Handled::Upstream(Upstream::Cached(query)),
         }
     }
 
-    pub async fn start(self) {
-        let addr = self.addr;
+    // returns how to answer a packet or None if it should not be answered
+    async fn handle_packet(&mut self, buf: Vec<u8>) -> Option<Handled> {
+        if buf.len() < 5 {
+            log::warn!("Received truncated packet from {}", self.addr);
+            self.strike(Strike::MalformedPacket).await;
+            return None;
+        }
 
-        let mut pool = CONNECTION_POOL.write().await;
-        pool.insert(addr, self.tx.clone());
-        drop(pool);
+        let header = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if header != SOURCE_PACKET_HEADER {
+            log::warn!(
+                "Received packet with invalid packet header from {}",
+                self.addr
+            );
+            self.strike(Strike::InvalidHeader).await;
+            return None;
+        }
 
-        tokio::spawn(async move {
-            if let Err(e) = self.handle_connection().await {
-                if e.kind() == std::io::ErrorKind::TimedOut {
-                    log::info!("Connection ({}) timed out: {}", addr, e);
-                } else {
-                    log::error!("Failed to handle connection ({}): {}", addr, e);
+        let header: QueryHeader = match QueryHeader::try_from(buf[4]) {
+            Ok(header) => header,
+            Err(e) => {
+                log::warn!("Received invalid packet from {}: {}", self.addr, e);
+                self.strike(Strike::InvalidHeader).await;
+                return None;
+            }
+        };
+
+        if header == QueryHeader::A2SInfo {
+            let packet: A2SInfo = match A2SInfo::try_from(&buf.as_slice()[4..]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    log::warn!(
+                        "Received packet with invalid query header from {}: {}",
+                        self.addr,
+                        e
+                    );
+                    self.strike(Strike::MalformedPacket).await;
+                    return None;
                 }
+            };
+
+            if !self
+                .check_challenge(self.state.challenge_policy.info, packet.challenge)
+                .await
+            {
+                return Some(Handled::Response(self.challenge_response()));
             }
 
+            Some(self.cached(CachedQuery::Info).await)
+        } else if header == QueryHeader::A2SPlayer {
+            let packet: A2SPlayer = match A2SPlayer::try_from(&buf.as_slice()[4..]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    log::warn!("Received invalid packet from {}: {}", self.addr, e);
+                    self.strike(Strike::MalformedPacket).await;
+                    return None;
+                }
+            };
+
+            if !self
+                .check_challenge(self.state.challenge_policy.player, packet.challenge)
+                .await
             {
-                let mut pool = CONNECTION_POOL.write().await;
-                if pool.contains_key(&addr) {
-                    pool.remove(&addr);
+                return Some(Handled::Response(self.challenge_response()));
+            }
+
+            Some(self.cached(CachedQuery::Player).await)
+        } else if header == QueryHeader::A2SRules {
+            let packet: A2SRules = match A2SRules::try_from(&buf.as_slice()[4..]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    log::warn!("Received invalid packet from {}: {}", self.addr, e);
+                    self.strike(Strike::MalformedPacket).await;
+                    return None;
                 }
+            };
+
+            if !self
+                .check_challenge(self.state.challenge_policy.rules, packet.challenge)
+                .await
+            {
+                return Some(Handled::Response(self.challenge_response()));
+            }
+
+            Some(self.cached(CachedQuery::Rules).await)
+        } else {
+            Some(Handled::Upstream(Upstream::Proxy(buf)))
+        }
+    }
+
+    /// Answers a packet if that does not need the upstream server, otherwise
+    /// returns it to be completed once the upstream replied.
+    pub async fn handle(mut self, buf: Vec<u8>) -> Option<Deferred> {
+        log::trace!("Received {} bytes from {}", buf.len(), self.addr);
+        let request_len = buf.len();
+
+        let response = match self.handle_packet(buf).await? {
+            Handled::Response(response) => response,
+            Handled::Upstream(upstream) => {
+                return Some(Deferred {
+                    connection: self,
+                    request_len,
+                    upstream,
+                })
             }
-        });
+        };
+
+        if let Err(e) = self.respond(request_len, response).await {
+            log::error!("Failed to respond to {}: {}", self.addr, e);
+        }
+
+        None
     }
 }
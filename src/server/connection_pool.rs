@@ -0,0 +1,72 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time,
+};
+
+use caches::{Cache, PutResult, RawLRU};
+
+use super::challenge::CHALLENGE_WINDOW;
+
+pub const DEFAULT_MAX_CLIENTS: usize = 65536;
+// a client stays verified for as long as its challenge is accepted
+pub const VERIFIED_TTL: time::Duration = time::Duration::from_secs(CHALLENGE_WINDOW.as_secs() * 2);
+
+#[derive(Debug, Clone)]
+struct Client {
+    verified_until: time::Instant,
+}
+
+// what a server knows about the clients it talked to, bounded so a flood of
+// spoofed addresses cannot grow it without limit; the least recently seen client
+// is evicted once it is full
+#[derive(Debug)]
+pub struct ConnectionPool {
+    clients: Mutex<RawLRU<SocketAddr, Client>>,
+    evicted: AtomicU64,
+}
+
+impl ConnectionPool {
+    pub fn new(max_clients: usize) -> Self {
+        Self {
+            clients: Mutex::new(RawLRU::new(max_clients.max(1)).unwrap()),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether `addr` recently presented a valid challenge.
+    pub fn is_verified(&self, addr: &SocketAddr) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+
+        match clients.get(addr) {
+            Some(client) if client.verified_until > time::Instant::now() => true,
+            Some(_) => {
+                clients.remove(addr);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn verify(&self, addr: SocketAddr) {
+        let client = Client {
+            verified_until: time::Instant::now() + VERIFIED_TTL,
+        };
+
+        if let PutResult::Evicted { key, .. } = self.clients.lock().unwrap().put(addr, client) {
+            log::trace!("Evicted {} from the connection pool", key);
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
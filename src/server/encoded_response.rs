@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::client::packets::{
+    split_packet::SplitPacket, QueryProtocol, SOURCE_PACKET_HEADER, SOURCE_SIMPLE_PACKET_MAX_SIZE,
+    SOURCE_SPLIT_PACKET_HEADER,
+};
+
+pub fn with_header(data: Vec<u8>) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(4 + data.len());
+    bytes.extend(SOURCE_PACKET_HEADER.to_le_bytes().iter());
+    bytes.extend(data);
+
+    bytes
+}
+
+// a response framed into the datagrams that are sent to the client, cheap to
+// clone so cached responses can be sent without encoding them again
+#[derive(Debug, Clone)]
+pub struct EncodedResponse {
+    datagrams: Arc<[Bytes]>,
+    len: usize,
+}
+
+impl EncodedResponse {
+    /// Frames a simple packet including its -1 header, splitting it if it does
+    /// not fit into a single datagram.
+    pub fn new(packet: Vec<u8>, protocol: QueryProtocol) -> Result<Self, std::io::Error> {
+        if packet.len() <= SOURCE_SIMPLE_PACKET_MAX_SIZE {
+            return Ok(Self::single(packet));
+        }
+
+        let datagrams: Vec<Bytes> = SplitPacket::split(rand::random::<i32>(), &packet, protocol)?
+            .into_iter()
+            .map(|packet| {
+                let mut bytes: Vec<u8> = SOURCE_SPLIT_PACKET_HEADER.to_le_bytes().to_vec();
+                bytes.extend(packet.encode(protocol));
+                Bytes::from(bytes)
+            })
+            .collect();
+        let len = datagrams.iter().map(|datagram| datagram.len()).sum();
+
+        Ok(Self {
+            datagrams: datagrams.into(),
+            len,
+        })
+    }
+
+    /// Frames a packet that always fits into a single datagram, e.g. a challenge.
+    pub fn single(packet: Vec<u8>) -> Self {
+        Self {
+            len: packet.len(),
+            datagrams: Arc::new([Bytes::from(packet)]),
+        }
+    }
+
+    pub fn datagrams(&self) -> &[Bytes] {
+        &self.datagrams
+    }
+
+    /// Total size of all datagrams.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    client::packets::{a2s_info_reply::A2SInfoReply, gs_info_reply::GSInfoReply},
+    config::InfoRewriteConfig,
+};
+
+const EDF_PORT: u8 = 0x80;
+const EDF_KEYWORDS: u8 = 0x20;
+
+fn render(template: &str, name: &str, map: &str, players: u8, max_players: u8, bots: u8) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{map}", map)
+        .replace("{players}", &players.to_string())
+        .replace("{maxPlayers}", &max_players.to_string())
+        .replace("{bots}", &bots.to_string())
+}
+
+pub fn rewrite_a2s_info(config: &InfoRewriteConfig, info: &mut A2SInfoReply) {
+    if config.hide_bots {
+        info.players = info.players.saturating_sub(info.bots);
+        info.bots = 0;
+    }
+
+    if let Some(template) = &config.name {
+        info.name = render(
+            template,
+            &info.name,
+            &info.map,
+            info.players,
+            info.max_players,
+            info.bots,
+        );
+    }
+
+    if let Some(visibility) = config.visibility {
+        info.visibility = visibility;
+    }
+
+    if let Some(append) = &config.append_keywords {
+        info.keywords = Some(match info.keywords.take() {
+            Some(keywords) if !keywords.is_empty() => format!("{},{}", keywords, append),
+            _ => append.clone(),
+        });
+        info.edf |= EDF_KEYWORDS;
+    }
+
+    if let Some(port) = config.port {
+        info.port = Some(port as i16);
+        info.edf |= EDF_PORT;
+    }
+}
+
+pub fn rewrite_gs_info(config: &InfoRewriteConfig, info: &mut GSInfoReply) {
+    if config.hide_bots {
+        info.players = info.players.saturating_sub(info.bots);
+        info.bots = 0;
+    }
+
+    if let Some(template) = &config.name {
+        info.name = render(
+            template,
+            &info.name,
+            &info.map,
+            info.players,
+            info.max_players,
+            info.bots,
+        );
+    }
+
+    if let Some(visibility) = config.visibility {
+        info.visibility = visibility;
+    }
+
+    // GoldSource has no port field, but reports the address including the port
+    if let Some(port) = config.port {
+        if let Some((host, _)) = info.address.rsplit_once(':') {
+            info.address = format!("{}:{}", host, port);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFilterResult {
+    Allowed,
+    Denied,
+    Unlisted,
+}
+
+#[derive(Debug, Default)]
+pub struct IpFilter {
+    allowlist: Vec<IpNet>,
+    denylist: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new(allowlist: &[String], denylist: &[String]) -> std::io::Result<Self> {
+        Ok(Self {
+            allowlist: Self::parse(allowlist)?,
+            denylist: Self::parse(denylist)?,
+        })
+    }
+
+    fn parse(ranges: &[String]) -> std::io::Result<Vec<IpNet>> {
+        ranges
+            .iter()
+            .map(|range| {
+                // plain addresses are treated as single host ranges
+                range
+                    .parse::<IpNet>()
+                    .or_else(|e| range.parse::<IpAddr>().map(IpNet::from).map_err(|_| e))
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("Invalid CIDR range {}: {}", range, e),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Allowlisted ranges take precedence over denylisted ones.
+    pub fn check(&self, ip: IpAddr) -> IpFilterResult {
+        let ip = ip.to_canonical();
+
+        if self.allowlist.iter().any(|range| range.contains(&ip)) {
+            IpFilterResult::Allowed
+        } else if self.denylist.iter().any(|range| range.contains(&ip)) {
+            IpFilterResult::Denied
+        } else {
+            IpFilterResult::Unlisted
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allowlist: &[&str], denylist: &[&str]) -> IpFilter {
+        let list =
+            |ranges: &[&str]| -> Vec<String> { ranges.iter().map(|r| r.to_string()).collect() };
+        IpFilter::new(&list(allowlist), &list(denylist)).unwrap()
+    }
+
+    fn check(filter: &IpFilter, ip: &str) -> IpFilterResult {
+        filter.check(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn allowlist_takes_precedence_over_denylist() {
+        let filter = filter(&["10.0.0.5/32"], &["10.0.0.0/8", "2001:db8::/32"]);
+
+        assert_eq!(check(&filter, "10.0.0.5"), IpFilterResult::Allowed);
+        assert_eq!(check(&filter, "10.1.2.3"), IpFilterResult::Denied);
+        assert_eq!(check(&filter, "2001:db8::1"), IpFilterResult::Denied);
+        assert_eq!(check(&filter, "192.0.2.1"), IpFilterResult::Unlisted);
+    }
+
+    #[test]
+    fn matches_mapped_addresses_against_ipv4_ranges() {
+        let filter = filter(&["192.0.2.0/24"], &["10.0.0.0/8"]);
+
+        assert_eq!(check(&filter, "::ffff:192.0.2.7"), IpFilterResult::Allowed);
+        assert_eq!(check(&filter, "::ffff:10.0.0.1"), IpFilterResult::Denied);
+    }
+
+    #[test]
+    fn parses_bare_addresses_as_single_hosts() {
+        let filter = filter(&[], &["192.0.2.1", "2001:db8::1"]);
+
+        assert_eq!(check(&filter, "192.0.2.1"), IpFilterResult::Denied);
+        assert_eq!(check(&filter, "192.0.2.2"), IpFilterResult::Unlisted);
+        assert_eq!(check(&filter, "2001:db8::1"), IpFilterResult::Denied);
+        assert_eq!(check(&filter, "2001:db8::2"), IpFilterResult::Unlisted);
+
+        let invalid = |range: &str| IpFilter::new(&[], &[range.to_string()]).is_err();
+        assert!(invalid("192.0.2.0/33"));
+        assert!(invalid("not an address"));
+    }
+}
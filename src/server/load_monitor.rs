@@ -0,0 +1,58 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time,
+};
+
+pub const SAMPLE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// packets per second received by a server, sampled once per SAMPLE_INTERVAL
+#[derive(Debug)]
+pub struct LoadMonitor {
+    packets: Arc<AtomicU64>,
+    rate: Arc<AtomicU64>,
+}
+
+impl LoadMonitor {
+    pub fn new() -> Self {
+        let instance = Self {
+            packets: Arc::new(AtomicU64::new(0)),
+            rate: Arc::new(AtomicU64::new(0)),
+        };
+
+        instance.start_sample_task();
+
+        instance
+    }
+
+    pub fn record(&self) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    fn start_sample_task(&self) {
+        let packets = Arc::downgrade(&self.packets);
+        let rate = self.rate.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let packets = match packets.upgrade() {
+                    Some(packets) => packets,
+                    None => break,
+                };
+
+                let count = packets.swap(0, Ordering::Relaxed);
+                rate.store(count / SAMPLE_INTERVAL.as_secs().max(1), Ordering::Relaxed);
+            }
+        });
+    }
+}
@@ -1,92 +1,504 @@
-mod challenge_cache;
+mod ban_list;
+mod challenge;
 mod connection;
+mod connection_pool;
+mod encoded_response;
+mod info_rewrite;
+mod ip_filter;
+mod load_monitor;
+mod offline;
+mod player_filter;
 mod query_cache;
+mod rate_limiter;
+mod rules_filter;
+mod socket;
+mod state;
+mod stats;
+mod status;
 
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time,
+};
 
-use tokio::net::UdpSocket;
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, oneshot, Mutex},
+    task::{JoinHandle, JoinSet},
+};
 
 use crate::{
-    client::{packets::SOURCE_SIMPLE_PACKET_MAX_SIZE, SteamQueryClient},
+    client::SteamQueryClient,
     config::ServerConfig,
-    server::connection::{Connection, CONNECTION_POOL},
+    metrics::Histogram,
+    server::connection::{Connection, Deferred},
+};
+
+use self::{
+    ban_list::{BanList, Strike},
+    challenge::ChallengeGenerator,
+    connection_pool::{ConnectionPool, DEFAULT_MAX_CLIENTS},
+    ip_filter::IpFilterResult,
+    load_monitor::LoadMonitor,
+    query_cache::CacheStats,
+    query_cache::QueryCacheManager,
+    rate_limiter::RateLimiter,
+    socket::{BatchReceiver, DEFAULT_BATCH_SIZE, DEFAULT_SOCKETS},
+    state::ServerState,
+    stats::ServerStats,
 };
 
-use self::{challenge_cache::ChallengeCache, query_cache::QueryCacheManager};
+pub use self::status::ServerStatus;
+pub(crate) use self::{
+    ban_list::{DEFAULT_BAN_DURATION, DEFAULT_MAX_BAN_DURATION},
+    ip_filter::IpFilter,
+};
+
+pub const DEFAULT_AMPLIFICATION_RATIO: f64 = 1.0;
+pub const DEFAULT_WORKERS: usize = 16;
+pub const QUEUE_CAPACITY: usize = 4096;
+pub const MAX_UPSTREAM_REQUESTS: usize = 256;
+
+pub const RECV_ERROR_BACKOFF: time::Duration = time::Duration::from_millis(100);
+
+// a packet that passed filtering, the socket it arrived on, its sender and
+// whether the sender is allowlisted
+type QueuedPacket = (Vec<u8>, Arc<UdpSocket>, SocketAddr, bool);
+
+// the listen task of a started server and how to stop it
+struct Listener {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
 
 pub struct SteamQueryCacheServer {
-    config: ServerConfig,
-    client: Arc<SteamQueryClient>,
-    socket: Arc<UdpSocket>,
-    challenge_cache: Arc<ChallengeCache>,
-    query_cache: Arc<QueryCacheManager>,
+    name: String,
+    // replaced by reloads while the server keeps running
+    state: std::sync::RwLock<Arc<ServerState>>,
+    sockets: std::sync::Mutex<Vec<Arc<UdpSocket>>>,
+    listener: std::sync::Mutex<Option<Listener>>,
 }
 
 impl SteamQueryCacheServer {
     pub async fn new(config: ServerConfig) -> std::io::Result<Self> {
-        let socket: Arc<UdpSocket> = Arc::new(UdpSocket::bind(config.bind.clone()).await?);
-        let client: Arc<SteamQueryClient> =
-            Arc::new(SteamQueryClient::new(config.host.clone()).await?);
-        let challenge_cache: Arc<ChallengeCache> = Arc::new(ChallengeCache::new().await);
-        let query_cache: Arc<QueryCacheManager> = Arc::new(QueryCacheManager::new(client.clone()));
+        let sockets = Self::bind(&config).await?;
+        let state = Self::build_state(config, None).await?;
+
         Ok(Self {
-            config,
-            socket,
+            name: state.config.name.clone(),
+            state: std::sync::RwLock::new(Arc::new(state)),
+            sockets: std::sync::Mutex::new(sockets),
+            listener: std::sync::Mutex::new(None),
+        })
+    }
+
+    async fn bind(config: &ServerConfig) -> std::io::Result<Vec<Arc<UdpSocket>>> {
+        let socket_count = config.sockets.unwrap_or(DEFAULT_SOCKETS).max(1);
+        if socket_count > 1 && !cfg!(target_os = "linux") {
+            log::warn!("Multiple sockets per bind address are only supported on Linux");
+        }
+        let mut sockets: Vec<Arc<UdpSocket>> = Vec::with_capacity(socket_count);
+        for _ in 0..socket_count {
+            sockets.push(Arc::new(
+                socket::bind(&config.bind, socket_count > 1).await?,
+            ));
+            if !cfg!(target_os = "linux") {
+                break;
+            }
+        }
+
+        Ok(sockets)
+    }
+
+    // a reload carries over the upstream client and cached values if the upstream
+    // stayed the same, and everything else whose config did not change
+    async fn build_state(
+        config: ServerConfig,
+        previous: Option<&ServerState>,
+    ) -> std::io::Result<ServerState> {
+        let ip_filter: IpFilter = IpFilter::new(&config.allowlist, &config.denylist)?;
+        let client: Arc<SteamQueryClient> = match previous {
+            Some(previous)
+                if previous.config.host == config.host
+                    && previous.config.protocol == config.protocol =>
+            {
+                previous.client.clone()
+            }
+            _ => Arc::new(SteamQueryClient::new(config.host.clone(), config.protocol).await?),
+        };
+        let query_cache: QueryCacheManager = QueryCacheManager::new(
+            client.clone(),
+            &config,
+            previous.map(|previous| &previous.query_cache),
+        )
+        .await;
+        let rate_limiter: Option<Arc<RateLimiter>> = match previous {
+            Some(previous) if previous.config.rate_limit == config.rate_limit => {
+                previous.rate_limiter.clone()
+            }
+            _ => config
+                .rate_limit
+                .as_ref()
+                .map(|rate_limit| Arc::new(RateLimiter::new(rate_limit))),
+        };
+        let ban_list: Option<Arc<BanList>> = match (previous, &config.ban) {
+            (Some(previous), _) if previous.config.ban == config.ban => previous.ban_list.clone(),
+            (_, Some(ban)) => Some(Arc::new(BanList::new(ban).await)),
+            (_, None) => None,
+        };
+        let connections: Arc<ConnectionPool> = match previous {
+            Some(previous) if previous.config.max_clients == config.max_clients => {
+                previous.connections.clone()
+            }
+            _ => Arc::new(ConnectionPool::new(
+                config.max_clients.unwrap_or(DEFAULT_MAX_CLIENTS),
+            )),
+        };
+
+        Ok(ServerState {
             client,
-            challenge_cache,
+            protocol: config.protocol,
+            challenges: match previous {
+                Some(previous) => previous.challenges.clone(),
+                None => Arc::new(ChallengeGenerator::new()),
+            },
+            challenge_policy: config.challenge.clone(),
+            amplification_ratio: config
+                .amplification_ratio
+                .unwrap_or(DEFAULT_AMPLIFICATION_RATIO),
+            load: match previous {
+                Some(previous) => previous.load.clone(),
+                None => Arc::new(LoadMonitor::new()),
+            },
             query_cache,
+            ban_list,
+            rate_limiter,
+            ip_filter,
+            connections,
+            stats: match previous {
+                Some(previous) => previous.stats.clone(),
+                None => Arc::new(ServerStats::default()),
+            },
+            config,
         })
     }
 
-    pub async fn listen(&self) {
-        log::info!("Listening on {}", self.config.bind);
+    fn state(&self) -> Arc<ServerState> {
+        self.state.read().unwrap().clone()
+    }
+
+    /// Applies a changed `config` to the running server. Cached values,
+    /// counters and known clients are kept, the server only stops listening
+    /// for a moment if its sockets or tasks are configured differently.
+    pub async fn reload(self: &Arc<Self>, config: ServerConfig) -> std::io::Result<()> {
+        let current = self.state();
+        let state = Arc::new(Self::build_state(config, Some(&current)).await?);
+
+        let rebind = state.config.bind != current.config.bind
+            || state.config.sockets != current.config.sockets;
+        let restart = rebind
+            || state.config.workers != current.config.workers
+            || state.config.batch_size != current.config.batch_size;
+        if !restart {
+            *self.state.write().unwrap() = state;
+            return Ok(());
+        }
+
+        self.stop().await;
+        if rebind {
+            // the old sockets have to be closed first, they may use the same address
+            self.sockets.lock().unwrap().clear();
+            match Self::bind(&state.config).await {
+                Ok(sockets) => *self.sockets.lock().unwrap() = sockets,
+                Err(e) => {
+                    match Self::bind(&current.config).await {
+                        Ok(sockets) => {
+                            *self.sockets.lock().unwrap() = sockets;
+                            self.start();
+                        }
+                        Err(e) => log::error!(
+                            "Failed to bind {} again, server {} is stopped: {}",
+                            current.config.bind,
+                            self.name,
+                            e
+                        ),
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        *self.state.write().unwrap() = state;
+        self.start();
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> ServerConfig {
+        self.state().config.clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn status(&self) -> ServerStatus {
+        let state = self.state();
+        let (info, players, rules) = state.query_cache.status().await;
+
+        ServerStatus {
+            name: self.name.clone(),
+            host: state.config.host.clone(),
+            protocol: state.config.protocol,
+            info,
+            players,
+            rules,
+        }
+    }
+
+    pub fn packets_received(&self) -> Vec<(String, u64)> {
+        self.state().stats.packets.counts()
+    }
+
+    pub fn denied_packets(&self) -> u64 {
+        self.state().stats.denied.load(Ordering::Relaxed)
+    }
+
+    pub fn banned_packets(&self) -> u64 {
+        self.state().stats.banned.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limited_packets(&self) -> u64 {
+        self.state()
+            .rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.dropped())
+            .unwrap_or_default()
+    }
+
+    pub fn challenges_issued(&self) -> u64 {
+        self.state().challenges.issued()
+    }
+
+    pub fn challenges_rejected(&self) -> u64 {
+        self.state().challenges.rejected()
+    }
+
+    pub fn tracked_clients(&self) -> usize {
+        self.state().connections.len()
+    }
+
+    pub fn evicted_clients(&self) -> u64 {
+        self.state().connections.evicted()
+    }
+
+    pub fn overloaded_packets(&self) -> u64 {
+        self.state().stats.overloaded.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_stats(&self) -> [(&'static str, Arc<CacheStats>); 3] {
+        self.state().query_cache.stats()
+    }
+
+    pub fn upstream_latency(&self) -> Arc<Histogram> {
+        self.state().client.latency()
+    }
+
+    pub fn upstream_timeouts(&self) -> u64 {
+        self.state().client.timeouts()
+    }
+
+    pub fn upstream_errors(&self) -> u64 {
+        self.state().client.errors()
+    }
+
+    fn overloaded(state: &ServerState, queue: &str) {
+        let total = state.stats.overloaded.fetch_add(1, Ordering::Relaxed) + 1;
+        if total.is_power_of_two() {
+            log::warn!(
+                "{} queue is full, dropped {} packets in total",
+                queue,
+                total
+            );
+        }
+    }
 
+    // workers share one queue and handle whichever packet comes next, packets
+    // waiting for the upstream are passed on, so they never block cache hits
+    async fn work(
+        server: Arc<Self>,
+        queue: Arc<Mutex<mpsc::Receiver<QueuedPacket>>>,
+        upstream: mpsc::Sender<Deferred>,
+    ) {
         loop {
-            let mut buf = Vec::with_capacity(SOURCE_SIMPLE_PACKET_MAX_SIZE);
-            let (_len, addr) = match self.socket.recv_buf_from(&mut buf).await {
-                Ok((len, addr)) => (len, addr),
+            let packet = queue.lock().await.recv().await;
+            let (buf, socket, addr, allowlisted) = match packet {
+                Some(packet) => packet,
+                None => break,
+            };
+
+            let state = server.state();
+            let deferred = Connection::new(state.clone(), socket, addr, allowlisted)
+                .handle(buf)
+                .await;
+            if let Some(deferred) = deferred {
+                if let Err(mpsc::error::TrySendError::Full(_)) = upstream.try_send(deferred) {
+                    Self::overloaded(&state, "Upstream");
+                }
+            }
+        }
+    }
+
+    // drops packets from denied, banned and rate limited addresses and returns
+    // whether the sender is allowlisted otherwise
+    async fn accept(state: &ServerState, addr: SocketAddr) -> Option<bool> {
+        let filter_result = state.ip_filter.check(addr.ip());
+        if filter_result == IpFilterResult::Denied {
+            log::trace!("Dropped packet from denylisted address {}", addr);
+            state.stats.denied.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let allowlisted = filter_result == IpFilterResult::Allowed;
+
+        if let Some(ban_list) = state.ban_list.as_ref().filter(|_| !allowlisted) {
+            if ban_list.is_banned(&addr.ip()).await {
+                log::trace!("Dropped packet from banned address {}", addr);
+                state.stats.banned.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+
+        if let Some(rate_limiter) = state.rate_limiter.as_ref().filter(|_| !allowlisted) {
+            if !rate_limiter.check(addr.ip()) {
+                log::trace!("Rate limited packet from {}", addr);
+                // spoofed floods would otherwise get their source addresses banned
+                if let Some(ban_list) = &state.ban_list {
+                    if state.connections.is_verified(&addr) {
+                        ban_list.strike(addr.ip(), Strike::RateLimited).await;
+                    }
+                }
+                return None;
+            }
+        }
+
+        Some(allowlisted)
+    }
+
+    async fn receive(
+        server: Arc<Self>,
+        socket: Arc<UdpSocket>,
+        batch_size: usize,
+        tx: mpsc::Sender<QueuedPacket>,
+    ) {
+        let mut receiver = BatchReceiver::new(batch_size);
+
+        loop {
+            let datagrams = match receiver.recv(&socket).await {
+                Ok(datagrams) => datagrams,
                 Err(e) => {
+                    // errors like ENOMEM tend to persist, so do not spin on them
                     log::error!("Failed to receive from socket: {}", e);
+                    tokio::time::sleep(RECV_ERROR_BACKOFF).await;
                     continue;
                 }
             };
 
-            let tx;
+            let state = server.state();
+            for (buf, addr) in datagrams {
+                state.load.record();
+                state.stats.packets.record(&buf);
 
-            {
-                let pool_item;
+                let allowlisted = match Self::accept(&state, addr).await {
+                    Some(allowlisted) => allowlisted,
+                    None => continue,
+                };
 
+                if let Err(mpsc::error::TrySendError::Full(_)) =
+                    tx.try_send((buf, socket.clone(), addr, allowlisted))
                 {
-                    let pool = CONNECTION_POOL.read().await;
-                    pool_item = match pool.get(&addr) {
-                        Some(tx) => Some(tx.clone()),
-                        None => None,
-                    };
-                }
-
-                match pool_item {
-                    Some(_tx) => {
-                        tx = _tx.clone();
-                    }
-                    None => {
-                        log::info!("New connection from {}", addr);
-                        let connection = Connection::new(
-                            self.socket.clone(),
-                            self.client.clone(),
-                            addr,
-                            self.challenge_cache.clone(),
-                            self.query_cache.clone(),
-                        )
-                        .await;
-                        tx = connection.tx.clone();
-                        connection.start().await;
-                    }
+                    Self::overloaded(&state, "Packet");
                 }
             }
+        }
+    }
+
+    /// Starts listening on the server's sockets in a task of its own.
+    pub fn start(self: &Arc<Self>) {
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(self.clone().listen(stopped));
 
-            if let Err(e) = tx.send(buf).await {
-                log::error!("Failed to send to channel: {}", e);
+        if let Some(previous) = self
+            .listener
+            .lock()
+            .unwrap()
+            .replace(Listener { stop, task })
+        {
+            previous.task.abort();
+        }
+    }
+
+    /// Stops listening and waits until no task uses the sockets anymore.
+    pub async fn stop(&self) {
+        let listener = self.listener.lock().unwrap().take();
+        if let Some(Listener { stop, task }) = listener {
+            let _ = stop.send(());
+            let _ = task.await;
+        }
+    }
+
+    /// Stops listening and closes the sockets, so their address can be bound
+    /// by another server.
+    pub async fn shutdown(&self) {
+        self.stop().await;
+        self.sockets.lock().unwrap().clear();
+    }
+
+    async fn listen(self: Arc<Self>, mut stopped: oneshot::Receiver<()>) {
+        let sockets = self.sockets.lock().unwrap().clone();
+        let config = self.state().config.clone();
+        log::info!(
+            "Listening on {} with {} socket(s)",
+            config.bind,
+            sockets.len()
+        );
+
+        let (tx, rx) = mpsc::channel::<QueuedPacket>(QUEUE_CAPACITY);
+        let queue = Arc::new(Mutex::new(rx));
+
+        let (upstream_tx, mut upstream_rx) = mpsc::channel::<Deferred>(MAX_UPSTREAM_REQUESTS);
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..config.workers.unwrap_or(DEFAULT_WORKERS).max(1) {
+            tasks.spawn(Self::work(self.clone(), queue.clone(), upstream_tx.clone()));
+        }
+        drop(upstream_tx);
+
+        let batch_size = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        for socket in sockets {
+            tasks.spawn(Self::receive(self.clone(), socket, batch_size, tx.clone()));
+        }
+        drop(tx);
+
+        // packets waiting for the upstream are completed here, at most
+        // MAX_UPSTREAM_REQUESTS at once
+        let mut upstream = JoinSet::new();
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                deferred = upstream_rx.recv(), if upstream.len() < MAX_UPSTREAM_REQUESTS => {
+                    match deferred {
+                        Some(deferred) => upstream.spawn(deferred.complete()),
+                        None => break,
+                    };
+                }
+                Some(_) = upstream.join_next(), if !upstream.is_empty() => {}
             }
         }
+
+        // waits for the tasks to finish, so none of them holds a socket anymore
+        upstream.shutdown().await;
+        tasks.shutdown().await;
+        log::info!("Stopped listening on {}", config.bind);
     }
 }
@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+
+use crate::{
+    client::packets::{
+        a2s_info_reply::A2SInfoReply, a2s_player_reply::A2SPlayerReply,
+        a2s_rules_reply::A2SRulesReply, gs_info_reply::GSInfoReply, QueryHeader,
+    },
+    config::OfflineConfig,
+};
+
+// responses served in place of the upstream's while it is unreachable
+
+pub const DEFAULT_OFFLINE_AFTER: u64 = 30;
+pub const DEFAULT_OFFLINE_NAME: &str = "Server offline";
+
+pub fn a2s_info(config: &OfflineConfig) -> A2SInfoReply {
+    A2SInfoReply {
+        header: QueryHeader::A2SInfoReply,
+        protocol: 17,
+        name: config
+            .name
+            .clone()
+            .unwrap_or(DEFAULT_OFFLINE_NAME.to_string()),
+        map: config.map.clone().unwrap_or_default(),
+        folder: config.folder.clone().unwrap_or_default(),
+        game: config.game.clone().unwrap_or_default(),
+        id: config.app_id.unwrap_or_default(),
+        players: 0,
+        max_players: config.max_players.unwrap_or_default(),
+        bots: 0,
+        server_type: b'd',
+        environment: b'l',
+        visibility: 0,
+        vac: 0,
+        version: "1.0.0.0".to_string(),
+        edf: 0,
+        port: None,
+        steam_id: None,
+        source_tv_port: None,
+        source_tv_name: None,
+        keywords: None,
+        game_id: None,
+    }
+}
+
+// `upstream` is the resolved upstream address, which clients should connect to
+// rather than the cacher's bind address
+pub fn gs_info(config: &OfflineConfig, upstream: SocketAddr) -> GSInfoReply {
+    GSInfoReply {
+        header: QueryHeader::GSInfo,
+        address: config.address.clone().unwrap_or(upstream.to_string()),
+        name: config
+            .name
+            .clone()
+            .unwrap_or(DEFAULT_OFFLINE_NAME.to_string()),
+        map: config.map.clone().unwrap_or_default(),
+        folder: config.folder.clone().unwrap_or_default(),
+        game: config.game.clone().unwrap_or_default(),
+        players: 0,
+        max_players: config.max_players.unwrap_or_default(),
+        protocol: 47,
+        server_type: b'd',
+        environment: b'l',
+        visibility: 0,
+        mod_info: None,
+        vac: 0,
+        bots: 0,
+    }
+}
+
+pub fn a2s_player() -> A2SPlayerReply {
+    A2SPlayerReply {
+        header: QueryHeader::A2SPlayerReply,
+        num_players: 0,
+        players: Vec::new(),
+    }
+}
+
+pub fn a2s_rules() -> A2SRulesReply {
+    A2SRulesReply {
+        header: QueryHeader::A2SRulesReply,
+        num_rules: 0,
+        rules: Vec::new(),
+    }
+}
@@ -0,0 +1,182 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    client::packets::a2s_player_reply::A2SPlayerReply,
+    config::{PlayerFilterConfig, PlayerNameMode},
+};
+
+pub const DEFAULT_PLACEHOLDER: &str = "Player {index}";
+
+#[derive(Debug)]
+pub struct PlayerFilter {
+    config: PlayerFilterConfig,
+    // hashed names are only stable while the process runs and cannot be
+    // looked up in a precomputed table
+    key: [u8; 32],
+}
+
+impl PlayerFilter {
+    pub fn new(config: &PlayerFilterConfig) -> Self {
+        Self {
+            config: config.clone(),
+            key: rand::random(),
+        }
+    }
+
+    fn hash(&self, name: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length is valid");
+        mac.update(name.as_bytes());
+
+        mac.finalize().into_bytes()[..4]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    pub fn apply(&self, reply: &mut A2SPlayerReply) {
+        // players that are still connecting have no name yet
+        if self.config.hide_unnamed {
+            reply.players.retain(|player| !player.name.is_empty());
+        }
+
+        if let Some(max_players) = self.config.max_players {
+            reply.players.truncate(max_players as usize);
+        }
+
+        for (index, player) in reply.players.iter_mut().enumerate() {
+            match self.config.names {
+                PlayerNameMode::Keep => {}
+                PlayerNameMode::Strip => player.name.clear(),
+                PlayerNameMode::Hash => player.name = self.hash(&player.name),
+                PlayerNameMode::Placeholder => {
+                    player.name = self
+                        .config
+                        .placeholder
+                        .as_deref()
+                        .unwrap_or(DEFAULT_PLACEHOLDER)
+                        .replace("{index}", &(index + 1).to_string())
+                }
+            }
+
+            if let Some(round) = self.config.round_duration.filter(|round| *round > 0) {
+                let round = round as f32;
+                player.duration = (player.duration / round).round() * round;
+            }
+        }
+
+        reply.num_players = reply.players.len() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::packets::{a2s_player_reply::A2SPlayerInfo, QueryHeader};
+
+    use super::*;
+
+    fn reply(names: &[&str]) -> A2SPlayerReply {
+        A2SPlayerReply {
+            header: QueryHeader::A2SPlayerReply,
+            num_players: names.len() as u8,
+            players: names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| A2SPlayerInfo {
+                    index: index as u8,
+                    name: name.to_string(),
+                    score: 0,
+                    duration: 100.0 + index as f32 * 20.0,
+                })
+                .collect(),
+        }
+    }
+
+    fn apply(config: PlayerFilterConfig, names: &[&str]) -> A2SPlayerReply {
+        let mut reply = reply(names);
+        PlayerFilter::new(&config).apply(&mut reply);
+        reply
+    }
+
+    fn names(reply: &A2SPlayerReply) -> Vec<&str> {
+        reply
+            .players
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect()
+    }
+
+    fn with_names(names: PlayerNameMode) -> PlayerFilterConfig {
+        PlayerFilterConfig {
+            names,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_and_strips_names() {
+        let reply = apply(with_names(PlayerNameMode::Keep), &["alice", "bob"]);
+        assert_eq!(names(&reply), ["alice", "bob"]);
+
+        let reply = apply(with_names(PlayerNameMode::Strip), &["alice", "bob"]);
+        assert_eq!(names(&reply), ["", ""]);
+    }
+
+    #[test]
+    fn hashes_names_stably() {
+        let filter = PlayerFilter::new(&with_names(PlayerNameMode::Hash));
+        let mut first = reply(&["alice", "bob"]);
+        let mut second = reply(&["bob", "alice"]);
+        filter.apply(&mut first);
+        filter.apply(&mut second);
+
+        assert_eq!(first.players[0].name.len(), 8);
+        assert!(!names(&first).contains(&"alice"));
+        assert_ne!(first.players[0].name, first.players[1].name);
+        assert_eq!(first.players[0].name, second.players[1].name);
+
+        // another process, or filter, hashes differently
+        let mut other = reply(&["alice"]);
+        PlayerFilter::new(&with_names(PlayerNameMode::Hash)).apply(&mut other);
+        assert_ne!(other.players[0].name, first.players[0].name);
+    }
+
+    #[test]
+    fn replaces_names_with_placeholders() {
+        let reply = apply(with_names(PlayerNameMode::Placeholder), &["alice", "bob"]);
+        assert_eq!(names(&reply), ["Player 1", "Player 2"]);
+
+        let config = PlayerFilterConfig {
+            names: PlayerNameMode::Placeholder,
+            placeholder: Some("#{index}".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(names(&apply(config, &["alice", "bob"])), ["#1", "#2"]);
+    }
+
+    #[test]
+    fn hides_unnamed_players_and_caps_the_list() {
+        let config = PlayerFilterConfig {
+            hide_unnamed: true,
+            max_players: Some(2),
+            ..Default::default()
+        };
+        let reply = apply(config, &["", "alice", "", "bob", "carol"]);
+
+        assert_eq!(names(&reply), ["alice", "bob"]);
+        assert_eq!(reply.num_players, 2);
+    }
+
+    #[test]
+    fn rounds_durations() {
+        let config = PlayerFilterConfig {
+            round_duration: Some(60),
+            ..Default::default()
+        };
+        let reply = apply(config, &["alice", "bob", "carol"]);
+
+        let durations: Vec<f32> = reply.players.iter().map(|player| player.duration).collect();
+        assert_eq!(durations, [120.0, 120.0, 120.0]);
+        assert_eq!(reply.num_players, 3);
+    }
+}
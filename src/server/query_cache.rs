@@ -1,97 +1,656 @@
-use std::{sync::Arc, time};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time,
+};
 
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
-use crate::client::{
-    packets::{
-        a2s_info::A2SInfo, a2s_info_reply::A2SInfoReply, a2s_player::A2SPlayer,
-        a2s_player_reply::A2SPlayerReply, a2s_rules::A2SRules, a2s_rules_reply::A2SRulesReply,
-        SourceQueryRequest, SourceQueryResponse,
+use super::{
+    encoded_response::{with_header, EncodedResponse},
+    info_rewrite::{rewrite_a2s_info, rewrite_gs_info},
+    offline,
+    player_filter::PlayerFilter,
+    rules_filter::RulesFilter,
+    status::{CachedStatus, InfoStatus},
+};
+use crate::{
+    client::{
+        packets::{
+            a2s_info::A2SInfo, a2s_info_reply::A2SInfoReply, a2s_player::A2SPlayer,
+            a2s_player_reply::A2SPlayerReply, a2s_rules::A2SRules, a2s_rules_reply::A2SRulesReply,
+            gs_info_reply::GoldSourceInfoReply, SourceQueryRequest, SourceQueryResponse,
+        },
+        SteamQueryClient, QUERY_TIMEOUT,
     },
-    SteamQueryClient,
+    config::{CacheMode, QueryProtocol, ServerConfig},
 };
 
-pub const DEFAULT_REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(5);
+pub const DEFAULT_INFO_TTL: u64 = 10;
+pub const DEFAULT_PLAYER_TTL: u64 = 5;
+pub const DEFAULT_RULES_TTL: u64 = 60;
+pub const MIN_REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// io::Error is not Clone, so waiters receive the kind and message instead
+type RefreshResult<Response> = Result<Arc<CacheEntry<Response>>, (std::io::ErrorKind, String)>;
+type InflightRefresh<Response> =
+    std::sync::Mutex<Option<broadcast::Sender<RefreshResult<Response>>>>;
+
+struct InflightGuard<'a, Response> {
+    inflight: &'a InflightRefresh<Response>,
+}
+
+impl<Response> Drop for InflightGuard<'_, Response> {
+    fn drop(&mut self) {
+        if let Ok(mut inflight) = self.inflight.lock() {
+            inflight.take();
+        }
+    }
+}
+
+// applied to every response fetched from upstream before it is cached
+pub struct Transform<Response>(Box<dyn Fn(&mut Response) + Send + Sync>);
+
+impl<Response> std::fmt::Debug for Transform<Response> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Transform")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub hits: AtomicU64,
+    pub stale_hits: AtomicU64,
+    pub offline_hits: AtomicU64,
+    pub misses: AtomicU64,
+    // upstream queries, shared by all requests waiting for them
+    pub refreshes: AtomicU64,
+    pub refresh_failures: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct CacheEntry<Response> {
+    // as received from upstream, so a reload can apply a changed transform
+    pub raw: Response,
+    pub value: Response,
+    pub encoded: EncodedResponse,
+    pub fetched: time::Instant,
+}
+
+// the queries answered from the cache, info is answered in the server's protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachedQuery {
+    Info,
+    Player,
+    Rules,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse<Response> {
+    pub entry: Arc<CacheEntry<Response>>,
+    pub stale: bool,
+}
 
 #[derive(Debug)]
 pub struct QueryCache<Request: SourceQueryRequest, Response: SourceQueryResponse> {
-    val: RwLock<Option<(Response, time::Instant)>>,
+    // last good response
+    val: RwLock<Option<Arc<CacheEntry<Response>>>>,
+    protocol: QueryProtocol,
     refresh_interval: time::Duration,
+    stale_ttl: time::Duration,
+    mode: CacheMode,
+    inflight: InflightRefresh<Response>,
+    transform: Option<Transform<Response>>,
+    stats: Arc<CacheStats>,
+    offline: Option<(time::Duration, Arc<CacheEntry<Response>>)>,
     client: Arc<SteamQueryClient>,
     _phantom: std::marker::PhantomData<Request>,
 }
 
-impl<Request: SourceQueryRequest, Response: SourceQueryResponse> QueryCache<Request, Response>
+impl<Request, Response> QueryCache<Request, Response>
 where
+    Request: SourceQueryRequest + Send + Sync + 'static,
+    Response: SourceQueryResponse + Send + Sync + 'static,
     for<'a> <Response as TryFrom<&'a [u8]>>::Error: std::fmt::Display,
 {
-    pub fn new(client: Arc<SteamQueryClient>, refresh_interval: Option<time::Duration>) -> Self {
+    pub fn new(
+        client: Arc<SteamQueryClient>,
+        protocol: QueryProtocol,
+        refresh_interval: time::Duration,
+        stale_ttl: time::Duration,
+        mode: CacheMode,
+    ) -> Self {
         Self {
             val: RwLock::new(None),
-            refresh_interval: refresh_interval.unwrap_or(DEFAULT_REFRESH_INTERVAL),
+            protocol,
+            refresh_interval,
+            stale_ttl,
+            mode,
+            inflight: std::sync::Mutex::new(None),
+            transform: None,
+            stats: Arc::new(CacheStats::default()),
+            offline: None,
             client,
             _phantom: std::marker::PhantomData,
         }
     }
 
-    pub async fn query_cached(&self) -> Result<Response, std::io::Error> {
-        {
-            let val = self.val.read().await;
-            if let Some((val, expiration)) = val.as_ref() {
-                if time::Instant::now() < *expiration {
-                    log::info!("Using cached value");
-                    return Ok(val.clone());
+    pub fn with_transform(
+        mut self,
+        transform: impl Fn(&mut Response) + Send + Sync + 'static,
+    ) -> Self {
+        self.transform = Some(Transform(Box::new(transform)));
+        self
+    }
+
+    /// Serves `value` once the upstream failed for at least `after`.
+    pub fn with_offline_response(mut self, after: time::Duration, value: Response) -> Self {
+        let encoded = match EncodedResponse::new(with_header(value.clone().into()), self.protocol) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                log::error!(
+                    "Invalid offline {:?} response: {}",
+                    Response::packet_header(),
+                    e
+                );
+                return self;
+            }
+        };
+        self.offline = Some((
+            after,
+            Arc::new(CacheEntry {
+                raw: value.clone(),
+                value,
+                encoded,
+                fetched: time::Instant::now(),
+            }),
+        ));
+        self
+    }
+
+    /// Continues the statistics of `previous` and, if it queried the same
+    /// upstream, its cached value with this cache's transform applied.
+    pub async fn with_previous(mut self, previous: &Self) -> Self {
+        self.stats = previous.stats.clone();
+
+        if Arc::ptr_eq(&self.client, &previous.client) {
+            if let Some(entry) = previous.cached().await {
+                // a transform may have made the value too large to send
+                self.val = RwLock::new(self.entry(entry.raw.clone(), entry.fetched).ok());
+            }
+        }
+
+        self
+    }
+
+    fn entry(
+        &self,
+        raw: Response,
+        fetched: time::Instant,
+    ) -> Result<Arc<CacheEntry<Response>>, std::io::Error> {
+        let mut value = raw.clone();
+        if let Some(Transform(transform)) = &self.transform {
+            transform(&mut value);
+        }
+
+        // encoded once here, so cache hits only clone the framed datagrams
+        let encoded = EncodedResponse::new(with_header(value.clone().into()), self.protocol)?;
+        Ok(Arc::new(CacheEntry {
+            raw,
+            value,
+            encoded,
+            fetched,
+        }))
+    }
+
+    /// The last value fetched from upstream, however old it is.
+    pub async fn cached(&self) -> Option<Arc<CacheEntry<Response>>> {
+        self.val.read().await.clone()
+    }
+
+    fn offline_response(&self) -> Option<Arc<CacheEntry<Response>>> {
+        let (after, entry) = self.offline.as_ref()?;
+        if self.client.failing_for()? >= *after {
+            Some(entry.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Answers from the cache without waiting for the upstream, None if it has
+    /// to be queried first.
+    pub async fn lookup(self: &Arc<Self>) -> Option<CachedResponse<Response>> {
+        // in background mode the refresh task keeps the value current, so it only
+        // counts as expired once a refresh had the chance to finish and did not
+        let fresh_for = match self.mode {
+            CacheMode::Lazy => self.refresh_interval,
+            CacheMode::Background => self.refresh_interval + QUERY_TIMEOUT,
+        };
+
+        let cached = self.val.read().await.clone();
+        if let Some(entry) = cached.as_ref() {
+            let age = entry.fetched.elapsed();
+            if age < fresh_for {
+                log::trace!("Using cached value");
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(CachedResponse {
+                    entry: entry.clone(),
+                    stale: false,
+                });
+            }
+
+            // lazy caches refresh in the background meanwhile, so clients do not
+            // wait for an upstream that may not answer at all
+            if age < fresh_for + self.stale_ttl {
+                log::trace!("Using stale cached value ({}s old)", age.as_secs());
+                if self.mode == CacheMode::Lazy {
+                    self.spawn_refresh();
                 }
+                self.stats.stale_hits.fetch_add(1, Ordering::Relaxed);
+                return Some(CachedResponse {
+                    entry: entry.clone(),
+                    // only marked while the upstream fails, a lazy cache serves
+                    // expired values during every refresh
+                    stale: self.mode == CacheMode::Background
+                        || self.client.failing_for().is_some(),
+                });
             }
         }
 
-        let val = self
+        // answer right away instead of letting every client wait for the upstream
+        // to time out again, a refresh in the background notices when it is back
+        if let Some(entry) = self.offline_response() {
+            self.spawn_refresh();
+
+            log::trace!("Using offline {:?} response", Response::packet_header());
+            self.stats.offline_hits.fetch_add(1, Ordering::Relaxed);
+            return Some(CachedResponse {
+                entry,
+                stale: false,
+            });
+        }
+
+        None
+    }
+
+    pub async fn query_cached(
+        self: &Arc<Self>,
+    ) -> Result<CachedResponse<Response>, std::io::Error> {
+        // another request may have refreshed the value meanwhile
+        if let Some(cached) = self.lookup().await {
+            return Ok(cached);
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        match self.refresh().await {
+            Ok(entry) => Ok(CachedResponse {
+                entry,
+                stale: false,
+            }),
+            Err(e) => match self.offline_response() {
+                Some(entry) => {
+                    log::warn!(
+                        "Failed to refresh {:?}, serving offline response: {}",
+                        Response::packet_header(),
+                        e
+                    );
+                    Ok(CachedResponse {
+                        entry,
+                        stale: false,
+                    })
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn spawn_refresh(self: &Arc<Self>) {
+        if self.inflight.lock().unwrap().is_some() {
+            return;
+        }
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cache.refresh().await {
+                log::warn!("Failed to refresh {:?}: {}", Response::packet_header(), e);
+            }
+        });
+    }
+
+    // concurrent refreshes share a single upstream query and all receive its result
+    async fn refresh(&self) -> Result<Arc<CacheEntry<Response>>, std::io::Error> {
+        let (tx, mut rx) = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.as_ref() {
+                Some(tx) => (None, tx.subscribe()),
+                None => {
+                    let (tx, rx) = broadcast::channel(1);
+                    inflight.replace(tx.clone());
+                    (Some(tx), rx)
+                }
+            }
+        };
+
+        let tx = match tx {
+            Some(tx) => tx,
+            None => {
+                log::debug!(
+                    "Waiting for in-flight {:?} refresh",
+                    Response::packet_header()
+                );
+                return match rx.recv().await {
+                    Ok(Ok(val)) => Ok(val),
+                    Ok(Err((kind, message))) => Err(std::io::Error::new(kind, message)),
+                    Err(e) => Err(std::io::Error::other(format!(
+                        "In-flight refresh was aborted: {}",
+                        e
+                    ))),
+                };
+            }
+        };
+
+        let guard = InflightGuard {
+            inflight: &self.inflight,
+        };
+
+        let result = self
             .client
             .query::<Request, Response>(Request::new())
-            .await?;
-        self.val
-            .write()
             .await
-            .replace((val.clone(), time::Instant::now() + self.refresh_interval));
+            .and_then(|value| self.entry(value, time::Instant::now()));
+        match &result {
+            Ok(entry) => {
+                self.val.write().await.replace(entry.clone());
+                self.stats.refreshes.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.stats.refresh_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        drop(guard);
+        let _ = tx.send(match &result {
+            Ok(val) => Ok(val.clone()),
+            Err(e) => Err((e.kind(), e.to_string())),
+        });
+
+        result
+    }
+
+    pub fn start_refresh_task(self: &Arc<Self>) {
+        let cache = Arc::downgrade(self);
+        // interval() panics on a zero period, a TTL of 0 refreshes every second
+        let refresh_interval = self.refresh_interval.max(MIN_REFRESH_INTERVAL);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(refresh_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
 
-        Ok(val)
+                let cache = match cache.upgrade() {
+                    Some(cache) => cache,
+                    None => break,
+                };
+
+                // a panic while parsing a reply only ends this refresh, not the task
+                match tokio::spawn(async move { cache.refresh().await }).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => log::warn!(
+                        "Failed to refresh {:?} in background: {}",
+                        Response::packet_header(),
+                        e
+                    ),
+                    Err(e) => log::error!(
+                        "Background {:?} refresh failed: {}",
+                        Response::packet_header(),
+                        e
+                    ),
+                }
+            }
+        });
     }
 }
 
+// only the info reply of the configured protocol is cached
+#[derive(Debug)]
+enum InfoCache {
+    Source(Arc<QueryCache<A2SInfo, A2SInfoReply>>),
+    GoldSource(Arc<QueryCache<A2SInfo, GoldSourceInfoReply>>),
+}
+
 #[derive(Debug)]
 pub struct QueryCacheManager {
-    a2s_info: QueryCache<A2SInfo, A2SInfoReply>,
-    a2s_player: QueryCache<A2SPlayer, A2SPlayerReply>,
-    a2s_rules: QueryCache<A2SRules, A2SRulesReply>,
+    info: InfoCache,
+    a2s_player: Arc<QueryCache<A2SPlayer, A2SPlayerReply>>,
+    a2s_rules: Arc<QueryCache<A2SRules, A2SRulesReply>>,
+    stale_name_suffix: Option<String>,
+    stale_players: Option<u8>,
+    protocol: QueryProtocol,
 }
 
 impl QueryCacheManager {
-    pub fn new(client: Arc<SteamQueryClient>) -> Self {
-        Self {
-            a2s_info: QueryCache::<A2SInfo, A2SInfoReply>::new(
-                client.clone(),
-                Some(time::Duration::from_secs(10)),
-            ),
-            a2s_player: QueryCache::<A2SPlayer, A2SPlayerReply>::new(
-                client.clone(),
-                Some(time::Duration::from_secs(5)),
-            ),
-            a2s_rules: QueryCache::<A2SRules, A2SRulesReply>::new(
-                client.clone(),
-                Some(time::Duration::from_secs(60)),
-            ),
+    /// Builds the caches of a server, continuing those of `previous` if the
+    /// server was reloaded.
+    pub async fn new(
+        client: Arc<SteamQueryClient>,
+        server_config: &ServerConfig,
+        previous: Option<&QueryCacheManager>,
+    ) -> Self {
+        let config = &server_config.cache;
+        let protocol = server_config.protocol;
+        let info_rewrite = server_config.info_rewrite.clone();
+        let player_filter = PlayerFilter::new(&server_config.player_filter);
+        let rules_filter = RulesFilter::new(&server_config.rules_filter);
+        let info_ttl = time::Duration::from_secs(config.info_ttl.unwrap_or(DEFAULT_INFO_TTL));
+        let player_ttl = time::Duration::from_secs(config.player_ttl.unwrap_or(DEFAULT_PLAYER_TTL));
+        let rules_ttl = time::Duration::from_secs(config.rules_ttl.unwrap_or(DEFAULT_RULES_TTL));
+        let stale_ttl = time::Duration::from_secs(config.stale_ttl.unwrap_or_default());
+        let mode = config.mode.unwrap_or_default();
+        let offline = server_config.offline.as_ref().map(|offline_config| {
+            let after = offline_config
+                .after
+                .unwrap_or(offline::DEFAULT_OFFLINE_AFTER);
+            (time::Duration::from_secs(after), offline_config)
+        });
+
+        let info = match protocol {
+            QueryProtocol::Source => {
+                let mut cache =
+                    QueryCache::new(client.clone(), protocol, info_ttl, stale_ttl, mode)
+                        .with_transform(move |info| rewrite_a2s_info(&info_rewrite, info));
+                if let Some((after, offline_config)) = offline {
+                    cache = cache.with_offline_response(after, offline::a2s_info(offline_config));
+                }
+                if let Some(InfoCache::Source(previous)) = previous.map(|previous| &previous.info) {
+                    cache = cache.with_previous(previous).await;
+                }
+                InfoCache::Source(Arc::new(cache))
+            }
+            QueryProtocol::GoldSource => {
+                let mut cache =
+                    QueryCache::new(client.clone(), protocol, info_ttl, stale_ttl, mode)
+                        .with_transform(move |info| match info {
+                            GoldSourceInfoReply::Obsolete(info) => {
+                                rewrite_gs_info(&info_rewrite, info)
+                            }
+                            GoldSourceInfoReply::Source(info) => {
+                                rewrite_a2s_info(&info_rewrite, info)
+                            }
+                        });
+                if let Some((after, offline_config)) = offline {
+                    let gs_info = offline::gs_info(offline_config, client.addr());
+                    cache =
+                        cache.with_offline_response(after, GoldSourceInfoReply::Obsolete(gs_info));
+                }
+                if let Some(InfoCache::GoldSource(previous)) =
+                    previous.map(|previous| &previous.info)
+                {
+                    cache = cache.with_previous(previous).await;
+                }
+                InfoCache::GoldSource(Arc::new(cache))
+            }
+        };
+        let mut a2s_player = QueryCache::new(client.clone(), protocol, player_ttl, stale_ttl, mode)
+            .with_transform(move |reply| player_filter.apply(reply));
+        let mut a2s_rules = QueryCache::new(client.clone(), protocol, rules_ttl, stale_ttl, mode)
+            .with_transform(move |reply| rules_filter.apply(reply));
+
+        if let Some((after, _)) = offline {
+            a2s_player = a2s_player.with_offline_response(after, offline::a2s_player());
+            a2s_rules = a2s_rules.with_offline_response(after, offline::a2s_rules());
+        }
+
+        if let Some(previous) = previous {
+            a2s_player = a2s_player.with_previous(&previous.a2s_player).await;
+            a2s_rules = a2s_rules.with_previous(&previous.a2s_rules).await;
+        }
+
+        let instance = Self {
+            info,
+            a2s_player: Arc::new(a2s_player),
+            a2s_rules: Arc::new(a2s_rules),
+            stale_name_suffix: config.stale_name_suffix.clone(),
+            stale_players: config.stale_players,
+            protocol,
+        };
+
+        if mode == CacheMode::Background {
+            match &instance.info {
+                InfoCache::Source(cache) => cache.start_refresh_task(),
+                InfoCache::GoldSource(cache) => cache.start_refresh_task(),
+            }
+            instance.a2s_player.start_refresh_task();
+            instance.a2s_rules.start_refresh_task();
+        }
+
+        instance
+    }
+
+    pub async fn status(
+        &self,
+    ) -> (
+        Option<CachedStatus<InfoStatus>>,
+        Option<CachedStatus<A2SPlayerReply>>,
+        Option<CachedStatus<A2SRulesReply>>,
+    ) {
+        fn status<T: Clone, U>(entry: Arc<CacheEntry<T>>, map: impl Fn(T) -> U) -> CachedStatus<U> {
+            CachedStatus {
+                value: map(entry.value.clone()),
+                age: entry.fetched.elapsed().as_secs_f64(),
+            }
+        }
+
+        let info = match &self.info {
+            InfoCache::Source(cache) => cache
+                .cached()
+                .await
+                .map(|entry| status(entry, InfoStatus::Source)),
+            InfoCache::GoldSource(cache) => cache
+                .cached()
+                .await
+                .map(|entry| status(entry, InfoStatus::GoldSource)),
+        };
+        let players = self
+            .a2s_player
+            .cached()
+            .await
+            .map(|entry| status(entry, |value| value));
+        let rules = self
+            .a2s_rules
+            .cached()
+            .await
+            .map(|entry| status(entry, |value| value));
+
+        (info, players, rules)
+    }
+
+    pub fn stats(&self) -> [(&'static str, Arc<CacheStats>); 3] {
+        let info = match &self.info {
+            InfoCache::Source(cache) => cache.stats.clone(),
+            InfoCache::GoldSource(cache) => cache.stats.clone(),
+        };
+
+        [
+            ("info", info),
+            ("player", self.a2s_player.stats.clone()),
+            ("rules", self.a2s_rules.stats.clone()),
+        ]
+    }
+
+    fn stale_modified(&self) -> bool {
+        self.stale_name_suffix.is_some() || self.stale_players.is_some()
+    }
+
+    /// Answers `query` from the cache without waiting for the upstream, None if
+    /// it has to be queried first.
+    pub async fn lookup(&self, query: CachedQuery) -> Option<EncodedResponse> {
+        match (query, &self.info) {
+            (CachedQuery::Info, InfoCache::Source(cache)) => {
+                Some(self.a2s_info_response(cache.lookup().await?))
+            }
+            (CachedQuery::Info, InfoCache::GoldSource(cache)) => {
+                Some(self.gs_info_response(cache.lookup().await?))
+            }
+            (CachedQuery::Player, _) => Some(self.a2s_player.lookup().await?.entry.encoded.clone()),
+            (CachedQuery::Rules, _) => Some(self.a2s_rules.lookup().await?.entry.encoded.clone()),
         }
     }
 
-    pub async fn a2s_info(&self) -> Result<A2SInfoReply, std::io::Error> {
-        self.a2s_info.query_cached().await
+    /// Answers `query` from the cache or the upstream.
+    pub async fn query(&self, query: CachedQuery) -> Result<EncodedResponse, std::io::Error> {
+        Ok(match (query, &self.info) {
+            (CachedQuery::Info, InfoCache::Source(cache)) => {
+                self.a2s_info_response(cache.query_cached().await?)
+            }
+            (CachedQuery::Info, InfoCache::GoldSource(cache)) => {
+                self.gs_info_response(cache.query_cached().await?)
+            }
+            (CachedQuery::Player, _) => self.a2s_player.query_cached().await?.entry.encoded.clone(),
+            (CachedQuery::Rules, _) => self.a2s_rules.query_cached().await?.entry.encoded.clone(),
+        })
     }
 
-    pub async fn a2s_player(&self) -> Result<A2SPlayerReply, std::io::Error> {
-        self.a2s_player.query_cached().await
+    fn a2s_info_response(&self, cached: CachedResponse<A2SInfoReply>) -> EncodedResponse {
+        if !cached.stale || !self.stale_modified() {
+            return cached.entry.encoded.clone();
+        }
+
+        let mut a2s_info = cached.entry.value.clone();
+        if let Some(suffix) = &self.stale_name_suffix {
+            a2s_info.name.push_str(suffix);
+        }
+        if let Some(players) = self.stale_players {
+            a2s_info.players = players;
+        }
+
+        // the unmodified reply is served if the changes make it too large
+        EncodedResponse::new(with_header(a2s_info.into()), self.protocol)
+            .unwrap_or_else(|_| cached.entry.encoded.clone())
     }
 
-    pub async fn a2s_rules(&self) -> Result<A2SRulesReply, std::io::Error> {
-        self.a2s_rules.query_cached().await
+    fn gs_info_response(&self, cached: CachedResponse<GoldSourceInfoReply>) -> EncodedResponse {
+        if !cached.stale || !self.stale_modified() {
+            return cached.entry.encoded.clone();
+        }
+
+        let mut gs_info = cached.entry.value.clone();
+        let (name, players) = match &mut gs_info {
+            GoldSourceInfoReply::Obsolete(info) => (&mut info.name, &mut info.players),
+            GoldSourceInfoReply::Source(info) => (&mut info.name, &mut info.players),
+        };
+        if let Some(suffix) = &self.stale_name_suffix {
+            name.push_str(suffix);
+        }
+        if let Some(stale_players) = self.stale_players {
+            *players = stale_players;
+        }
+
+        // the unmodified reply is served if the changes make it too large
+        EncodedResponse::new(with_header(gs_info.into()), self.protocol)
+            .unwrap_or_else(|_| cached.entry.encoded.clone())
     }
 }
@@ -0,0 +1,206 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time,
+};
+
+use caches::{Cache, PutResult, RawLRU};
+
+use crate::config::RateLimitConfig;
+
+pub const DEFAULT_IPV4_PREFIX: u8 = 32;
+pub const DEFAULT_IPV6_PREFIX: u8 = 128;
+// a flood of spoofed addresses evicts the least recently seen buckets instead
+// of growing the limiter without bound
+pub const MAX_BUCKETS: usize = 65536;
+pub const REPORT_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: time::Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<RawLRU<IpAddr, Bucket>>>,
+    rate: f64,
+    burst: f64,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    dropped: Arc<AtomicU64>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let instance = Self {
+            buckets: Arc::new(Mutex::new(RawLRU::new(MAX_BUCKETS).unwrap())),
+            rate: config.rate,
+            burst: config.burst.max(1) as f64,
+            ipv4_prefix: config.ipv4_prefix.unwrap_or(DEFAULT_IPV4_PREFIX).min(32),
+            ipv6_prefix: config.ipv6_prefix.unwrap_or(DEFAULT_IPV6_PREFIX).min(128),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+
+        instance.start_report_task();
+
+        instance
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn key(&self, ip: IpAddr) -> IpAddr {
+        // dual-stack sockets report IPv4 clients as mapped IPv6 addresses
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.ipv4_prefix as u32)
+                    .unwrap_or(0);
+                IpAddr::V4((u32::from(ip) & mask).into())
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.ipv6_prefix as u32)
+                    .unwrap_or(0);
+                IpAddr::V6((u128::from(ip) & mask).into())
+            }
+        }
+    }
+
+    /// Takes a token from the bucket of `ip` (or its prefix) and returns
+    /// whether the packet may be processed.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let now = time::Instant::now();
+        let key = self.key(ip);
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = match buckets.get_mut(&key) {
+            Some(bucket) => bucket,
+            None => {
+                if let PutResult::Evicted { key, .. } = buckets.put(
+                    key,
+                    Bucket {
+                        tokens: self.burst,
+                        updated: now,
+                    },
+                ) {
+                    log::trace!("Evicted the rate limit bucket of {}", key);
+                }
+                buckets.get_mut(&key).unwrap()
+            }
+        };
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn start_report_task(&self) {
+        let buckets = Arc::downgrade(&self.buckets);
+        let dropped = self.dropped.clone();
+
+        tokio::spawn(async move {
+            let mut reported: u64 = 0;
+
+            loop {
+                tokio::time::sleep(REPORT_INTERVAL).await;
+
+                if buckets.upgrade().is_none() {
+                    break;
+                }
+
+                let total = dropped.load(Ordering::Relaxed);
+                if total > reported {
+                    log::warn!(
+                        "Rate limit dropped {} packets in the last {}s ({} total)",
+                        total - reported,
+                        REPORT_INTERVAL.as_secs(),
+                        total
+                    );
+                    reported = total;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            rate,
+            burst,
+            ipv4_prefix: None,
+            ipv6_prefix: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn allows_burst_then_drops() {
+        let limiter = limiter(0.001, 3);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert!((0..3).all(|_| limiter.check(ip)));
+        assert!(!limiter.check(ip));
+        assert_eq!(limiter.dropped(), 1);
+
+        // other addresses have their own bucket
+        assert!(limiter.check("192.0.2.2".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn refills_tokens_over_time() {
+        let limiter = limiter(100.0, 1);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert!(limiter.check(ip));
+        assert!(!limiter.check(ip));
+
+        std::thread::sleep(time::Duration::from_millis(20));
+        assert!(limiter.check(ip));
+    }
+
+    #[tokio::test]
+    async fn shares_buckets_between_mapped_and_prefixed_addresses() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            rate: 0.001,
+            burst: 2,
+            ipv4_prefix: Some(24),
+            ipv6_prefix: None,
+        });
+
+        assert!(limiter.check("192.0.2.1".parse().unwrap()));
+        assert!(limiter.check("::ffff:192.0.2.1".parse().unwrap()));
+        assert!(!limiter.check("192.0.2.200".parse().unwrap()));
+        assert!(limiter.check("192.0.3.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_seen_bucket() {
+        let limiter = limiter(0.001, 1);
+
+        for i in 0..=MAX_BUCKETS as u32 {
+            assert!(limiter.check(IpAddr::V4(i.into())));
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+
+        // the first address was evicted and starts with a full bucket again
+        assert!(limiter.check(IpAddr::V4(0.into())));
+        assert!(!limiter.check(IpAddr::V4((MAX_BUCKETS as u32).into())));
+    }
+}
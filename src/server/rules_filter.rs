@@ -0,0 +1,117 @@
+use crate::{
+    client::packets::a2s_rules_reply::{A2SRule, A2SRulesReply},
+    config::RulesFilterConfig,
+};
+
+// matches cvar names case-insensitively against a pattern with `*` and `?`; a
+// mismatch after a `*` retries with the `*` consuming one more byte, which keeps
+// the matching linear in the pattern times the name length
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // pattern index after the last `*` and the name index it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(c) if *c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, n));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[derive(Debug)]
+pub struct RulesFilter {
+    config: RulesFilterConfig,
+}
+
+impl RulesFilter {
+    pub fn new(config: &RulesFilterConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    fn is_visible(&self, name: &str) -> bool {
+        let matches_any = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| matches(pattern.as_bytes(), name.as_bytes()))
+        };
+
+        (self.config.allow.is_empty() || matches_any(&self.config.allow))
+            && !matches_any(&self.config.deny)
+    }
+
+    pub fn apply(&self, reply: &mut A2SRulesReply) {
+        reply.rules.retain(|rule| {
+            self.is_visible(&rule.name) && !self.config.inject.contains_key(&rule.name)
+        });
+
+        reply
+            .rules
+            .extend(self.config.inject.iter().map(|(name, value)| A2SRule {
+                name: name.clone(),
+                value: value.clone(),
+            }));
+
+        reply.num_rules = reply.rules.len() as i16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn matches(pattern: &str, name: &str) -> bool {
+        super::matches(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn matches_literals_case_insensitively() {
+        assert!(matches("sv_cheats", "SV_Cheats"));
+        assert!(!matches("sv_cheats", "sv_cheat"));
+        assert!(!matches("sv_cheat", "sv_cheats"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("sv_*", "sv_gravity"));
+        assert!(matches("sv_*", "sv_"));
+        assert!(matches("*_version", "metamod_version"));
+        assert!(matches("*mod*", "metamod_version"));
+        assert!(matches("a*b*c", "axxbyybzzc"));
+        assert!(!matches("a*b*c", "axxbyybzz"));
+        assert!(matches("mp_?imelimit", "mp_timelimit"));
+        assert!(!matches("mp_?", "mp_"));
+        assert!(matches("*?", "x"));
+    }
+
+    #[test]
+    fn matches_empty_strings() {
+        assert!(matches("", ""));
+        assert!(matches("*", ""));
+        assert!(matches("**", ""));
+        assert!(!matches("?", ""));
+        assert!(!matches("", "sv_cheats"));
+    }
+
+    #[test]
+    fn matches_many_wildcards_quickly() {
+        let name = "a".repeat(200);
+        assert!(!matches(&format!("{}b", "*a".repeat(50)), &name));
+    }
+}
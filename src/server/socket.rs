@@ -0,0 +1,239 @@
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::client::packets::SOURCE_SIMPLE_PACKET_MAX_SIZE;
+
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+pub const DEFAULT_SOCKETS: usize = 1;
+
+// one byte more than any request may have, so truncated datagrams can be told apart
+const RECV_BUFFER_SIZE: usize = SOURCE_SIMPLE_PACKET_MAX_SIZE + 1;
+
+/// Binds a UDP socket to `addr`; with `reuse_port` several sockets can be bound
+/// to the same address and the kernel distributes incoming packets among them.
+pub async fn bind(addr: &str, reuse_port: bool) -> std::io::Result<UdpSocket> {
+    let addr: SocketAddr = match tokio::net::lookup_host(addr).await?.next() {
+        Some(addr) => addr,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Could not resolve bind address {}", addr),
+            ))
+        }
+    };
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    #[cfg(target_os = "linux")]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(target_os = "linux"))]
+    let _ = reuse_port;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Receives datagrams in batches of up to `batch_size`, using a single
+/// recvmmsg call per batch on Linux.
+#[derive(Debug)]
+pub struct BatchReceiver {
+    bufs: Vec<[u8; RECV_BUFFER_SIZE]>,
+}
+
+impl BatchReceiver {
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            bufs: vec![[0u8; RECV_BUFFER_SIZE]; batch_size.max(1)],
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn recv(
+        &mut self,
+        socket: &UdpSocket,
+    ) -> std::io::Result<Vec<(Vec<u8>, SocketAddr)>> {
+        if self.bufs.len() == 1 {
+            return self.recv_single(socket).await;
+        }
+
+        let bufs = &mut self.bufs;
+        socket
+            .async_io(tokio::io::Interest::READABLE, || mmsg::recv(socket, bufs))
+            .await
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn recv(
+        &mut self,
+        socket: &UdpSocket,
+    ) -> std::io::Result<Vec<(Vec<u8>, SocketAddr)>> {
+        self.recv_single(socket).await
+    }
+
+    async fn recv_single(
+        &mut self,
+        socket: &UdpSocket,
+    ) -> std::io::Result<Vec<(Vec<u8>, SocketAddr)>> {
+        let (len, addr) = socket.recv_from(&mut self.bufs[0]).await?;
+        if len > SOURCE_SIMPLE_PACKET_MAX_SIZE {
+            log::debug!("Dropped oversized datagram from {}", addr);
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![(self.bufs[0][..len].to_vec(), addr)])
+    }
+}
+
+/// Sends all `packets` to `addr`, using as few sendmmsg calls as possible on
+/// Linux.
+#[cfg(target_os = "linux")]
+pub async fn send_all(
+    socket: &UdpSocket,
+    packets: &[Bytes],
+    addr: SocketAddr,
+) -> std::io::Result<()> {
+    let mut sent = 0;
+    while sent < packets.len() {
+        sent += socket
+            .async_io(tokio::io::Interest::WRITABLE, || {
+                mmsg::send(socket, &packets[sent..], addr)
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_all(
+    socket: &UdpSocket,
+    packets: &[Bytes],
+    addr: SocketAddr,
+) -> std::io::Result<()> {
+    for packet in packets {
+        socket.send_to(packet, addr).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod mmsg {
+    use std::{net::SocketAddr, os::fd::AsRawFd};
+
+    use bytes::Bytes;
+    use socket2::SockAddr;
+    use tokio::net::UdpSocket;
+
+    use super::RECV_BUFFER_SIZE;
+
+    fn header(
+        name: *mut libc::c_void,
+        namelen: libc::socklen_t,
+        iov: *mut libc::iovec,
+    ) -> libc::mmsghdr {
+        // msghdr has private padding fields on some targets
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_name = name;
+        header.msg_namelen = namelen;
+        header.msg_iov = iov;
+        header.msg_iovlen = 1;
+
+        libc::mmsghdr {
+            msg_hdr: header,
+            msg_len: 0,
+        }
+    }
+
+    pub fn recv(
+        socket: &UdpSocket,
+        bufs: &mut [[u8; RECV_BUFFER_SIZE]],
+    ) -> std::io::Result<Vec<(Vec<u8>, SocketAddr)>> {
+        let mut addrs: Vec<libc::sockaddr_storage> =
+            vec![unsafe { std::mem::zeroed() }; bufs.len()];
+        let mut iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| {
+                header(
+                    addr as *mut libc::sockaddr_storage as *mut libc::c_void,
+                    std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+                    iov,
+                )
+            })
+            .collect();
+
+        let received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let datagrams = headers
+            .iter()
+            .zip(addrs)
+            .zip(bufs.iter())
+            .take(received as usize)
+            .filter_map(|((header, addr), buf)| {
+                let addr = unsafe { SockAddr::new(addr, header.msg_hdr.msg_namelen) };
+                let addr = addr.as_socket()?;
+                // datagrams larger than the buffer were cut off by the kernel
+                if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                    log::debug!("Dropped oversized datagram from {}", addr);
+                    return None;
+                }
+                let len = (header.msg_len as usize).min(buf.len());
+                Some((buf[..len].to_vec(), addr))
+            })
+            .collect();
+
+        Ok(datagrams)
+    }
+
+    pub fn send(socket: &UdpSocket, packets: &[Bytes], addr: SocketAddr) -> std::io::Result<usize> {
+        let addr = SockAddr::from(addr);
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iov| header(addr.as_ptr() as *mut libc::c_void, addr.len(), iov))
+            .collect();
+
+        let sent = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if sent < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(sent as usize)
+    }
+}
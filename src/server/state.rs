@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use crate::{
+    client::{packets::QueryProtocol, SteamQueryClient},
+    config::{ChallengeConfig, ServerConfig},
+};
+
+use super::{
+    ban_list::BanList, challenge::ChallengeGenerator, connection_pool::ConnectionPool,
+    ip_filter::IpFilter, load_monitor::LoadMonitor, query_cache::QueryCacheManager,
+    rate_limiter::RateLimiter, stats::ServerStats,
+};
+
+// everything the receive loops and workers of a server share; replaced as a
+// whole by a reload, which carries over the parts its config did not change
+#[derive(Debug)]
+pub struct ServerState {
+    pub config: ServerConfig,
+    pub client: Arc<SteamQueryClient>,
+    pub protocol: QueryProtocol,
+    pub challenges: Arc<ChallengeGenerator>,
+    pub challenge_policy: ChallengeConfig,
+    pub amplification_ratio: f64,
+    pub load: Arc<LoadMonitor>,
+    pub query_cache: QueryCacheManager,
+    pub ban_list: Option<Arc<BanList>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub ip_filter: IpFilter,
+    pub connections: Arc<ConnectionPool>,
+    pub stats: Arc<ServerStats>,
+}
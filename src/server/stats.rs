@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::client::packets::{QueryHeader, SOURCE_PACKET_HEADER};
+
+// received packets by their query header byte
+#[derive(Debug)]
+pub struct PacketCounter {
+    headers: [AtomicU64; 256],
+    // split packets and packets too short to have a header
+    other: AtomicU64,
+}
+
+impl Default for PacketCounter {
+    fn default() -> Self {
+        Self {
+            headers: std::array::from_fn(|_| AtomicU64::new(0)),
+            other: AtomicU64::new(0),
+        }
+    }
+}
+
+impl PacketCounter {
+    pub fn record(&self, buf: &[u8]) {
+        if buf.len() >= 5 && buf[..4] == SOURCE_PACKET_HEADER.to_le_bytes() {
+            self.headers[buf[4] as usize].fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.other.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(header, count)| (header as u8, count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .map(|(header, count)| match QueryHeader::try_from(header) {
+                Ok(header) => (format!("{:?}", header), count),
+                Err(_) => (format!("0x{:02X}", header), count),
+            })
+            .collect();
+
+        let other = self.other.load(Ordering::Relaxed);
+        if other > 0 {
+            counts.push(("Other".to_string(), other));
+        }
+
+        counts
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    pub packets: PacketCounter,
+    pub denied: AtomicU64,
+    pub banned: AtomicU64,
+    // packets dropped because the queue to the workers was full
+    pub overloaded: AtomicU64,
+}
@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+use crate::client::packets::{
+    a2s_info_reply::A2SInfoReply, a2s_player_reply::A2SPlayerReply, a2s_rules_reply::A2SRulesReply,
+    gs_info_reply::GoldSourceInfoReply, QueryProtocol,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedStatus<T> {
+    pub value: T,
+    // seconds since the value was fetched from upstream
+    pub age: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum InfoStatus {
+    Source(A2SInfoReply),
+    GoldSource(GoldSourceInfoReply),
+}
+
+// what the cache currently holds for a server, without querying the upstream
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub name: String,
+    pub host: String,
+    pub protocol: QueryProtocol,
+    pub info: Option<CachedStatus<InfoStatus>>,
+    pub players: Option<CachedStatus<A2SPlayerReply>>,
+    pub rules: Option<CachedStatus<A2SRulesReply>>,
+}
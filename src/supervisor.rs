@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time,
+};
+
+use crate::{Config, SteamQueryCacheServer};
+
+pub const WATCH_INTERVAL: time::Duration = time::Duration::from_secs(2);
+
+// the servers that are currently running, shared with the HTTP listener
+pub type ServerList = Arc<RwLock<Vec<Arc<SteamQueryCacheServer>>>>;
+
+// runs the servers of a config file and applies changes to it while running,
+// servers whose config did not change keep running untouched
+pub struct Supervisor {
+    path: String,
+    modified: Option<time::SystemTime>,
+    // in config order, so the HTTP listener lists the servers like the config
+    running: Vec<Arc<SteamQueryCacheServer>>,
+    servers: ServerList,
+}
+
+impl Supervisor {
+    pub async fn start(path: String, config: Config) -> std::io::Result<Self> {
+        let mut supervisor = Self {
+            modified: Self::modified(&path).await,
+            path,
+            running: Vec::new(),
+            servers: ServerList::default(),
+        };
+
+        for server in config.servers {
+            let server = Arc::new(SteamQueryCacheServer::new(server).await?);
+            server.start();
+            supervisor.running.push(server);
+        }
+        supervisor.publish();
+
+        Ok(supervisor)
+    }
+
+    pub fn servers(&self) -> ServerList {
+        self.servers.clone()
+    }
+
+    async fn modified(path: &str) -> Option<time::SystemTime> {
+        tokio::fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn publish(&self) {
+        *self.servers.write().unwrap() = self.running.clone();
+    }
+
+    // reloads the config whenever the file changes or SIGHUP is received
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                log::warn!("Failed to listen for SIGHUP: {}", e);
+                None
+            }
+        };
+
+        loop {
+            #[cfg(unix)]
+            let hangup_received = async {
+                match hangup.as_mut() {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup_received = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = interval.tick() => {
+                    let modified = Self::modified(&self.path).await;
+                    if modified == self.modified {
+                        continue;
+                    }
+                    self.modified = modified;
+                    log::info!("Config file {} changed, reloading", self.path);
+                }
+                _ = hangup_received => {
+                    self.modified = Self::modified(&self.path).await;
+                    log::info!("SIGHUP received, reloading {}", self.path);
+                }
+            }
+
+            self.reload().await;
+        }
+    }
+
+    async fn reload(&mut self) {
+        let config = match Config::load(self.path.clone()).await {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Failed to reload config, keeping the running one: {}", e);
+                return;
+            }
+        };
+
+        // removed servers are stopped first, so their addresses are free for
+        // changed and new ones
+        let mut previous: HashMap<String, Arc<SteamQueryCacheServer>> = HashMap::new();
+        for server in self.running.drain(..) {
+            match config
+                .servers
+                .iter()
+                .any(|config| config.name == server.name())
+            {
+                true => {
+                    previous.insert(server.name().to_string(), server);
+                }
+                false => {
+                    log::info!("Stopping removed server {}", server.name());
+                    server.shutdown().await;
+                }
+            }
+        }
+
+        // changed servers go before new ones, which may bind an address a changed
+        // server moved away from
+        for server in &config.servers {
+            let running = match previous.get(&server.name) {
+                Some(running) => running,
+                None => continue,
+            };
+            if running.config() == *server {
+                continue;
+            }
+
+            log::info!("Reloading changed server {}", server.name);
+            if let Err(e) = running.reload(server.clone()).await {
+                log::error!(
+                    "Failed to apply the changed config of server {}: {}",
+                    server.name,
+                    e
+                );
+            }
+        }
+
+        for server in config.servers {
+            let name = server.name.clone();
+            if let Some(running) = previous.remove(&name) {
+                self.running.push(running);
+                continue;
+            }
+
+            log::info!("Starting new server {}", name);
+            match SteamQueryCacheServer::new(server).await {
+                Ok(server) => {
+                    let server = Arc::new(server);
+                    server.start();
+                    self.running.push(server);
+                }
+                Err(e) => log::error!("Failed to start server {}: {}", name, e),
+            }
+        }
+
+        self.publish();
+    }
+}
@@ -3,11 +3,13 @@ use std::{hash::Hash, sync::Arc, time};
 use dashmap::DashMap;
 use tokio::sync::RwLock;
 
+type Entries<K, V> = Arc<RwLock<DashMap<K, (V, time::Instant, time::Duration)>>>;
+
 pub const DEFAULT_CLEANUP_INTERVAL: time::Duration = time::Duration::from_secs(60);
 
 #[derive(Debug)]
 pub struct TimedHashMap<K: Eq + Hash + Clone + Send + Sync, V: Clone + Send + Sync> {
-    inner: Arc<RwLock<DashMap<K, (V, time::Instant, time::Duration)>>>,
+    inner: Entries<K, V>,
     refresh_interval: bool,
     cleanup_interval: time::Duration,
 }
@@ -35,24 +37,38 @@ impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'sta
     }
 
     pub async fn get(&self, key: &K) -> Option<V> {
-        if let Some(temp) = self.inner.read().await.get(key) {
-            let (value, expiration, duration) = temp.value();
-            if time::Instant::now() < *expiration {
-                if self.refresh_interval {
-                    self.inner.write().await.insert(
-                        key.clone(),
-                        (value.clone(), time::Instant::now() + *duration, *duration),
-                    );
-                }
-                return Some(value.clone());
-            } else {
-                self.inner.write().await.remove(key);
+        // the read guard must be released before taking the write lock below
+        let entry = self
+            .inner
+            .read()
+            .await
+            .get(key)
+            .map(|entry| entry.value().clone());
+
+        let (value, expiration, duration) = entry?;
+        if time::Instant::now() < expiration {
+            if self.refresh_interval {
+                self.inner.write().await.insert(
+                    key.clone(),
+                    (value.clone(), time::Instant::now() + duration, duration),
+                );
             }
+            return Some(value);
         }
+
+        self.inner.write().await.remove(key);
         None
     }
 
-    async fn cleanup(map: &Arc<RwLock<DashMap<K, (V, time::Instant, time::Duration)>>>) {
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        self.inner
+            .write()
+            .await
+            .remove(key)
+            .map(|(_, (value, _, _))| value)
+    }
+
+    async fn cleanup(map: &Entries<K, V>) {
         map.write()
             .await
             .retain(|_, (_, expiration, _)| *expiration > time::Instant::now());